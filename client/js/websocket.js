@@ -148,6 +148,10 @@ function getWebSocketUri() {
   if(resource) {
     chat_path += "&resource=" + encodeURI(resource);
   }
+  let assignment = new URLSearchParams(window.location.search).get("assignment");
+  if(assignment) {
+    chat_path += "&assignment=" + encodeURIComponent(assignment);
+  }
 
   let websocket_uri =
     window.location.protocol === "https:"
@@ -243,6 +247,15 @@ async function stopRecording() {
   );
 }
 
+function showComparison(comparison) {
+  const coverage = document.getElementById("coverage");
+  if (coverage) {
+    coverage.textContent =
+      `Coverage ${Math.round(comparison.coverage * 100)}%, ` +
+      `${Math.round(comparison.progress * 100)}% of the speech so far`;
+  }
+}
+
 function initWebSocket(websocket_uri) {
   console.log(`Connecting to ${websocket_uri}`);
   try {
@@ -260,6 +273,11 @@ function initWebSocket(websocket_uri) {
     ws.addEventListener("message", (e) => {
       let message = JSON.parse(e.data);
       console.log(message);
+      if (message.comparison) {
+        // live comparison with the resource's reference
+        showComparison(message.comparison);
+        return;
+      }
       state.uuid = message.uuid;
       if (!message.sequence_number) {
         // control message
@@ -0,0 +1,50 @@
+# A stand-in for a webhook receiver, for trying out the notifications sent
+# when sessions are finished. Run it, start the server with
+# WEBHOOK_URLS=http://localhost:8000/ and the same WEBHOOK_SECRET, and it
+# prints each delivery and whether its signature is good.
+#
+# --fail N answers the first N requests with 503, to see them retried.
+import argparse
+import hashlib
+import hmac
+import json
+import os
+from http.server import BaseHTTPRequestHandler, HTTPServer
+
+parser = argparse.ArgumentParser()
+parser.add_argument("--port", type=int, default=8000)
+parser.add_argument("--fail", type=int, default=0)
+args = parser.parse_args()
+
+secret = os.environ.get("WEBHOOK_SECRET")
+failures = args.fail
+
+
+class Receiver(BaseHTTPRequestHandler):
+    def do_POST(self):
+        global failures
+        body = self.rfile.read(int(self.headers.get("Content-Length", 0)))
+        delivery = self.headers.get("X-Terplounge-Delivery")
+        if failures > 0:
+            failures -= 1
+            print(f"delivery {delivery}: failing on purpose")
+            self.send_response(503)
+            self.end_headers()
+            return
+
+        if secret is None:
+            verdict = "not checked, WEBHOOK_SECRET not set"
+        else:
+            expected = "sha256=" + hmac.new(
+                secret.encode(), body, hashlib.sha256
+            ).hexdigest()
+            signature = self.headers.get("X-Terplounge-Signature", "")
+            verdict = "good" if hmac.compare_digest(expected, signature) else "BAD"
+        print(f"delivery {delivery}: signature {verdict}")
+        print(json.dumps(json.loads(body), indent=2, ensure_ascii=False))
+        self.send_response(204)
+        self.end_headers()
+
+
+if __name__ == "__main__":
+    HTTPServer(("localhost", args.port), Receiver).serve_forever()
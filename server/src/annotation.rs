@@ -0,0 +1,130 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::User;
+use crate::db;
+use crate::error::{Er, E};
+use crate::session::SessionData;
+
+/// Which column of the comparison a quoted span of text is from.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Transcript,
+    Reference,
+}
+
+impl Side {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Side::Transcript => "transcript",
+            Side::Reference => "reference",
+        }
+    }
+}
+
+/// A comment on a session, anchored to a time range of the recording, to a
+/// quoted span of the transcript or reference, or to both.
+#[derive(Clone, Debug, Serialize)]
+pub struct Annotation {
+    pub id: usize,
+    #[serde(skip_serializing)]
+    pub author_id: Option<usize>,
+    pub author: Option<String>,
+    /// Seconds from the start of the recording.
+    pub start_time: Option<f64>,
+    pub end_time: Option<f64>,
+    pub side: Option<Side>,
+    pub quote: Option<String>,
+    pub comment: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct AnnotationRequest {
+    pub start_time: Option<f64>,
+    pub end_time: Option<f64>,
+    pub side: Option<Side>,
+    pub quote: Option<String>,
+    pub comment: String,
+}
+
+impl AnnotationRequest {
+    fn validate(&self) -> E<()> {
+        if self.comment.trim().is_empty() {
+            return Err(Er::new("Annotations need a comment".to_string()));
+        }
+        let quoted = self.side.is_some() && self.quote.as_ref().is_some_and(|q| !q.is_empty());
+        if self.start_time.is_none() && !quoted {
+            return Err(Er::new(
+                "Annotations need a start time, or a side and a quote".to_string(),
+            ));
+        }
+        if self.quote.is_some() != self.side.is_some() {
+            return Err(Er::new(
+                "A quote needs the side of the comparison it is from".to_string(),
+            ));
+        }
+        match (self.start_time, self.end_time) {
+            (Some(start), _) if start < 0.0 => {
+                Err(Er::new("Start time can't be negative".to_string()))
+            }
+            (Some(start), Some(end)) if end < start => {
+                Err(Er::new("End time can't be before start time".to_string()))
+            }
+            (None, Some(_)) => Err(Er::new("End time needs a start time".to_string())),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Annotation {
+    /// Annotations can be changed by their authors and admins. Those made
+    /// without logging in can be changed by anyone not logged in, in the
+    /// same way as sessions.
+    pub fn can_edit(&self, user: &Option<User>) -> bool {
+        match user {
+            Some(u) if u.is_admin() => true,
+            _ => self.author_id == user.as_ref().map(|u| u.id),
+        }
+    }
+}
+
+pub fn create(
+    session: &SessionData,
+    user: &Option<User>,
+    request: &AnnotationRequest,
+) -> E<Annotation> {
+    request.validate()?;
+    let id = db::insert_annotation(session.id, user.as_ref().map(|u| u.id), request)?;
+    log::debug!("Added annotation {} to session {}", id, session.uuid);
+    db::load_annotation(&session.id, &id)?.ok_or(Er::new(format!("Annotation {} not found", id)))
+}
+
+/// Returns `None` if there is no such annotation, or `user` can't change it.
+pub fn update(
+    session: &SessionData,
+    id: &usize,
+    user: &Option<User>,
+    request: &AnnotationRequest,
+) -> E<Option<Annotation>> {
+    match db::load_annotation(&session.id, id)? {
+        Some(a) if a.can_edit(user) => (),
+        _ => return Ok(None),
+    }
+    request.validate()?;
+    db::update_annotation(id, request)?;
+    db::load_annotation(&session.id, id)
+}
+
+/// Returns whether the annotation was deleted.
+pub fn delete(session: &SessionData, id: &usize, user: &Option<User>) -> E<bool> {
+    match db::load_annotation(&session.id, id)? {
+        Some(a) if a.can_edit(user) => {
+            db::delete_annotation(id)?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
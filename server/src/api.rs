@@ -1,14 +1,30 @@
-use crate::error::E;
+use crate::annotation::{self, AnnotationRequest};
+use crate::assignment::{self, Assignment, NewAssignment, Submission, SubmissionQuery};
+use crate::auth::{self, Credentials, Scope, TokenRequest, User};
+use crate::compare::{Change, CompareError};
+use crate::db::{SessionQuery, MAX_PER_PAGE};
+use crate::error::{reject, ApiError, E};
 use crate::metadata::Metadata;
-use crate::session::{get_sessions, mark_session_for_closure_uuid, user_connected, SessionData};
+use crate::normalize::Normalization;
+use crate::session::{
+    find_session_with_uuid, get_session, get_sessions, mark_session_for_closure_uuid,
+    star_session, user_connected, SessionData,
+};
+use crate::share::{self, ShareRequest};
+use crate::timed::TimedQuery;
 use askama::Template; // bring trait in scope
 use bytes::Bytes;
+use chrono::{DateTime, Utc};
 use rust_embed::RustEmbed;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
 use std::collections::HashMap;
-use std::io::Read;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use urlencoding::decode;
+use warp::http::{StatusCode, Uri};
 use warp::reply::Json;
+use warp::Reply;
 use warp::{http::Response, Filter};
 use warp_range::{filter_range, get_range};
 
@@ -16,19 +32,423 @@ use warp_range::{filter_range, get_range};
 #[template(path = "index.html", escape = "none")]
 pub struct Index {
     sessions: Vec<SessionData>,
+    user: Option<User>,
 }
 
-pub async fn index() -> std::result::Result<impl warp::Reply, warp::Rejection> {
-    let mut sessions = get_sessions().await.ok_or(warp::reject::reject())?;
+pub async fn index(
+    user: Option<User>,
+) -> std::result::Result<warp::reply::Response, warp::Rejection> {
+    if user.is_none() && auth::login_required() {
+        return Ok(warp::redirect::see_other(Uri::from_static("/login.html")).into_response());
+    }
+    let mut sessions = get_sessions(user.as_ref().map(|u| u.id))
+        .await
+        .ok_or(warp::reject::custom(ApiError::Storage))?;
     sessions.sort_by(|a, b| {
         a.created_at
             .partial_cmp(&b.created_at)
             .expect("Unexpected error in comparison")
     });
 
-    let template = Index { sessions };
+    let template = Index { sessions, user };
 
-    Ok(warp::reply::html(template.render().unwrap()))
+    Ok(render(&template)?.into_response())
+}
+
+/// Renders a page, rejecting the request if the template fails.
+fn render<T: Template>(
+    template: &T,
+) -> std::result::Result<warp::reply::Html<String>, warp::Rejection> {
+    match template.render() {
+        Ok(html) => Ok(warp::reply::html(html)),
+        Err(e) => {
+            log::error!("Error rendering template: {:?}", e);
+            Err(warp::reject::custom(ApiError::Internal))
+        }
+    }
+}
+
+fn not_found(message: String) -> warp::Rejection {
+    warp::reject::custom(ApiError::NotFound(message))
+}
+
+/// The largest request body accepted, in bytes. Bodies are forms and small
+/// JSON documents like annotations and assignments.
+const MAX_BODY_BYTES: u64 = 64 * 1024;
+
+/// A JSON body, rejecting ones over `MAX_BODY_BYTES`.
+fn json_body<T: DeserializeOwned + Send>(
+) -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone {
+    warp::body::content_length_limit(MAX_BODY_BYTES).and(warp::body::json())
+}
+
+/// A form body, rejecting ones over `MAX_BODY_BYTES`.
+fn form_body<T: DeserializeOwned + Send>(
+) -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone {
+    warp::body::content_length_limit(MAX_BODY_BYTES).and(warp::body::form())
+}
+
+/// Decodes the resource path of a route.
+fn decode_resource(resource_path: &str) -> std::result::Result<String, warp::Rejection> {
+    match decode(resource_path) {
+        Ok(path) => Ok(path.into_owned()),
+        Err(_) => Err(warp::reject::custom(ApiError::BadRequest(format!(
+            "Invalid URL encoding in {}",
+            resource_path
+        )))),
+    }
+}
+
+/// The `max_lag` of a timed comparison, which must be a number of seconds
+/// and not negative.
+fn max_lag(query: &TimedQuery) -> std::result::Result<f64, warp::Rejection> {
+    if query.max_lag.is_finite() && query.max_lag >= 0.0 {
+        Ok(query.max_lag)
+    } else {
+        Err(warp::reject::custom(ApiError::BadRequest(format!(
+            "max_lag must be a number of seconds, not {}",
+            query.max_lag
+        ))))
+    }
+}
+
+async fn lookup_session(uuid: &String) -> std::result::Result<SessionData, warp::Rejection> {
+    let session_id = find_session_with_uuid(uuid)
+        .await
+        .ok_or_else(|| not_found(format!("Session {} not found", uuid)))?;
+    get_session(&session_id)
+        .await
+        .ok_or_else(|| not_found(format!("Session {} not found", uuid)))
+}
+
+/// Looks up the session with the given uuid, if `user` may see it: their
+/// own sessions, and those recorded for assignments they set. Teachers can
+/// read and annotate their students' sessions, but nothing else, so routes
+/// which change a session use `find_own_session`.
+async fn find_session(
+    uuid: &String,
+    user: &Option<User>,
+) -> std::result::Result<SessionData, warp::Rejection> {
+    let session = lookup_session(uuid).await?;
+    if !session.is_visible_to(user) && !assignment::teaches(user, &session) {
+        return Err(not_found(format!("Session {} not found", uuid)));
+    }
+    Ok(session)
+}
+
+/// Looks up the session with the given uuid, if it belongs to `user`.
+async fn find_own_session(
+    uuid: &String,
+    user: &Option<User>,
+) -> std::result::Result<SessionData, warp::Rejection> {
+    let session = lookup_session(uuid).await?;
+    if !session.is_visible_to(user) {
+        return Err(not_found(format!("Session {} not found", uuid)));
+    }
+    Ok(session)
+}
+
+pub fn register(credentials: Credentials) -> warp::reply::Response {
+    match auth::register(&credentials) {
+        Ok(user) => {
+            log::info!("Registered user {}", user.username);
+            login(credentials)
+        }
+        Err(e) => ApiError::BadRequest(e.to_string()).into_response(),
+    }
+}
+
+pub fn login(credentials: Credentials) -> warp::reply::Response {
+    match auth::login(&credentials) {
+        Ok(token) => warp::reply::with_header(
+            warp::redirect::see_other(Uri::from_static("/")),
+            "Set-Cookie",
+            auth::login_cookie(&token),
+        )
+        .into_response(),
+        Err(e) => {
+            log::info!("Failed login for {}: {}", credentials.username, e);
+            ApiError::Unauthorized(e.to_string()).into_response()
+        }
+    }
+}
+
+pub fn logout(token: Option<String>) -> warp::reply::Response {
+    if let Some(token) = token {
+        if let Err(e) = auth::logout(&token) {
+            log::error!("Error logging out: {:?}", e);
+        }
+    }
+    warp::reply::with_header(
+        warp::redirect::see_other(Uri::from_static("/")),
+        "Set-Cookie",
+        auth::logout_cookie(),
+    )
+    .into_response()
+}
+
+#[derive(Serialize)]
+pub struct SessionList {
+    sessions: Vec<SessionData>,
+    total: usize,
+    page: usize,
+    per_page: usize,
+}
+
+pub fn create_token(user: User, request: TokenRequest) -> warp::reply::Response {
+    match auth::create_token(&user, &request) {
+        Ok(token) => warp::reply::json(&json!({
+            "token": token,
+            "name": request.name,
+            "scopes": request.scopes,
+        }))
+        .into_response(),
+        Err(e) => ApiError::Forbidden(e.to_string()).into_response(),
+    }
+}
+
+pub async fn list_tokens(user: User) -> std::result::Result<impl warp::Reply, warp::Rejection> {
+    match crate::db::list_api_tokens(&user.id) {
+        Ok(tokens) => Ok(warp::reply::json(&tokens)),
+        Err(e) => {
+            log::error!("Error listing tokens: {:?}", e);
+            Err(reject(e))
+        }
+    }
+}
+
+pub async fn delete_token(
+    id: usize,
+    user: User,
+) -> std::result::Result<impl warp::Reply, warp::Rejection> {
+    match crate::db::delete_api_token(&id, &user.id) {
+        Ok(true) => Ok("deleted"),
+        Ok(false) => Err(not_found(format!("Token {} not found", id))),
+        Err(e) => {
+            log::error!("Error deleting token {}: {:?}", id, e);
+            Err(reject(e))
+        }
+    }
+}
+
+pub async fn list_sessions(
+    mut query: SessionQuery,
+    user: Option<User>,
+) -> std::result::Result<impl warp::Reply, warp::Rejection> {
+    query.all_users = user.as_ref().is_some_and(|u| u.is_admin());
+    query.user_id = user.map(|u| u.id);
+    query.page = query.page.max(1);
+    query.per_page = query.per_page.clamp(1, MAX_PER_PAGE);
+    if query.offset().is_none() {
+        return Err(warp::reject::custom(ApiError::BadRequest(format!(
+            "Page {} is out of range",
+            query.page
+        ))));
+    }
+    match crate::db::query_sessions(&query) {
+        Ok((sessions, total)) => Ok(warp::reply::json(&SessionList {
+            sessions,
+            total,
+            page: query.page,
+            per_page: query.per_page,
+        })),
+        Err(e) => {
+            log::error!("Error listing sessions: {:?}", e);
+            Err(reject(e))
+        }
+    }
+}
+
+/// Looks up the assignment with the given id, if `user` set it.
+fn find_assignment(
+    id: &usize,
+    user: &Option<User>,
+) -> std::result::Result<Assignment, warp::Rejection> {
+    let assignment = match crate::db::load_assignment(id) {
+        Ok(Some(a)) => a,
+        Ok(None) => return Err(not_found(format!("Assignment {} not found", id))),
+        Err(e) => {
+            log::error!("Error loading assignment {}: {:?}", id, e);
+            return Err(reject(e));
+        }
+    };
+    if !assignment.is_teacher(user) {
+        return Err(not_found(format!("Assignment {} not found", id)));
+    }
+    Ok(assignment)
+}
+
+pub fn create_assignment(user: Option<User>, new: NewAssignment) -> warp::reply::Response {
+    let user = match user {
+        Some(u) => u,
+        None => return ApiError::Unauthorized("Login required".to_string()).into_response(),
+    };
+    match assignment::create(&user, &new) {
+        Ok(a) => warp::reply::json(&a).into_response(),
+        Err(e) => ApiError::BadRequest(e.to_string()).into_response(),
+    }
+}
+
+/// Teachers see the assignments they set, everyone else all of them, so
+/// students can pick the one they are working on.
+pub async fn list_assignments(
+    user: Option<User>,
+) -> std::result::Result<impl warp::Reply, warp::Rejection> {
+    let teacher_id = match &user {
+        Some(u) if u.has_scope(Scope::Teach) && !u.is_admin() => Some(u.id),
+        _ => None,
+    };
+    match crate::db::load_assignments(teacher_id) {
+        Ok(assignments) => Ok(warp::reply::json(&assignments)),
+        Err(e) => {
+            log::error!("Error listing assignments: {:?}", e);
+            Err(reject(e))
+        }
+    }
+}
+
+async fn assignment_submissions(
+    id: usize,
+    query: SubmissionQuery,
+    user: Option<User>,
+) -> std::result::Result<(Assignment, Vec<Submission>), warp::Rejection> {
+    let assignment = find_assignment(&id, &user)?;
+    let submissions = assignment::submissions(&assignment).await;
+    match submissions {
+        Ok(mut s) => {
+            assignment::sort_submissions(&mut s, query.sort);
+            Ok((assignment, s))
+        }
+        Err(e) => {
+            log::error!("Error loading submissions for assignment {}: {:?}", id, e);
+            Err(reject(e))
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Renders a comparison as the transcript and reference columns of
+/// `compare.html`.
+fn changes_html(changes: &[Change]) -> (String, String) {
+    let mut dest = String::new();
+    let mut source = String::new();
+    for change in changes {
+        let mut content = change.content.as_str();
+        while let Some(rest) = content
+            .strip_prefix('\n')
+            .or_else(|| content.strip_prefix('\r'))
+        {
+            dest.push_str("<br>");
+            source.push_str("<br>");
+            content = rest;
+        }
+        if content.is_empty() {
+            continue;
+        }
+        let content = escape_html(content);
+        match change.change_type.as_str() {
+            "delete" => dest.push_str(&format!(
+                "<span class=\"compare-delete\">{}</span>",
+                content
+            )),
+            "insert" => source.push_str(&format!(
+                "<span class=\"compare-insert\">{}</span>",
+                content
+            )),
+            "equal" => {
+                dest.push_str(&content);
+                source.push_str(&content);
+            }
+            _ => log::warn!("Unexpected change type {}", change.change_type),
+        }
+    }
+    (dest, source)
+}
+
+pub struct SubmissionView {
+    student: String,
+    uuid: String,
+    created_at: String,
+    late: bool,
+    state: String,
+    coverage: String,
+    wer: String,
+    bleu: String,
+    chrf: String,
+    dest: String,
+    source: String,
+}
+
+#[derive(Template)]
+#[template(path = "assignment.html", escape = "none")]
+pub struct AssignmentPage {
+    assignment: Assignment,
+    name: String,
+    resource: String,
+    submissions: Vec<SubmissionView>,
+}
+
+pub async fn assignment_page(
+    id: usize,
+    query: SubmissionQuery,
+    user: Option<User>,
+) -> std::result::Result<impl warp::Reply, warp::Rejection> {
+    let (assignment, submissions) = assignment_submissions(id, query, user).await?;
+    let submissions = submissions
+        .into_iter()
+        .map(|s| {
+            let (dest, source) = changes_html(&s.changes);
+            SubmissionView {
+                student: escape_html(s.student.as_deref().unwrap_or("anonymous")),
+                uuid: s.uuid.to_string(),
+                created_at: s.created_at.to_rfc3339(),
+                late: s.late,
+                state: s.state.as_str().to_string(),
+                coverage: format!("{:.0}", s.counts.coverage() * 100.0),
+                wer: format!("{:.0}", s.metrics.wer * 100.0),
+                bleu: format!("{:.1}", s.metrics.bleu * 100.0),
+                chrf: format!("{:.1}", s.metrics.chrf * 100.0),
+                dest,
+                source,
+            }
+        })
+        .collect();
+    let template = AssignmentPage {
+        name: escape_html(&assignment.name),
+        resource: escape_html(&assignment.resource),
+        assignment,
+        submissions,
+    };
+    render(&template)
+}
+
+pub async fn assignment_summary(
+    id: usize,
+    query: SubmissionQuery,
+    user: Option<User>,
+) -> std::result::Result<impl warp::Reply, warp::Rejection> {
+    let (assignment, submissions) = assignment_submissions(id, query, user).await?;
+    let response = match Response::builder()
+        .header("Content-Type", "text/csv; charset=utf-8")
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"assignment-{}.csv\"", assignment.id),
+        )
+        .body(assignment::summary_csv(&submissions))
+    {
+        Ok(r) => r,
+        Err(e) => {
+            log::error!("Error making response: {:?}", e);
+            return Err(warp::reject::custom(ApiError::Internal));
+        }
+    };
+    Ok(response)
 }
 
 #[derive(Template)]
@@ -43,15 +463,11 @@ pub async fn practice(
     resource_path: String,
     lang: String,
 ) -> std::result::Result<impl warp::Reply, warp::Rejection> {
-    let metadata = match Metadata::from_resource_path(
-        &decode(&resource_path)
-            .expect("invalide URL encoding")
-            .into_owned(),
-    ) {
+    let metadata = match Metadata::from_resource_path(&decode_resource(&resource_path)?) {
         Ok(m) => m,
         Err(e) => {
             log::error!("Error loading metadata in practise: {:?}", e);
-            return Err(warp::reject::not_found());
+            return Err(not_found(format!("Resource {} not found", resource_path)));
         }
     };
     let template = PracticeData {
@@ -60,7 +476,7 @@ pub async fn practice(
         lang,
     };
 
-    Ok(warp::reply::html(template.render().unwrap()))
+    render(&template)
 }
 
 #[derive(Template)]
@@ -75,7 +491,9 @@ pub async fn compare(
     resource_path: String,
     uuid: String,
     lang: String,
+    user: Option<User>,
 ) -> std::result::Result<impl warp::Reply, warp::Rejection> {
+    find_session(&uuid, &user).await?;
     let template = match crate::compare::get_comparison(&resource_path, &uuid, &lang).await {
         Ok(c) => Comparison {
             resource: c.resource,
@@ -84,40 +502,266 @@ pub async fn compare(
         },
         Err(e) => {
             log::error!("Couldn't get transcript for uuid {}: {:?}", uuid, e);
-            return Err(warp::reject::reject());
+            return Err(reject(e));
         }
     };
-    Ok(warp::reply::html(template.render().unwrap()))
+    render(&template)
+}
+
+/// Replies with the result of a comparison. Comparisons the client asked
+/// for which can't be made, for a language the resource hasn't got or a
+/// session which doesn't exist, are rejected with their `CompareError`, and
+/// explained in a body like `{"error": "bad_request", "message": "...",
+/// "reason": "missing_translation", "available": ["de"]}`.
+fn comparison_reply<T: Serialize>(
+    result: E<T>,
+    what: &str,
+) -> std::result::Result<warp::reply::Response, warp::Rejection> {
+    match result {
+        Ok(x) => Ok(warp::reply::json(&x).into_response()),
+        Err(e) => {
+            if !e.is::<CompareError>() {
+                log::error!("Error in {}: {:?}", what, e);
+            }
+            Err(reject(e))
+        }
+    }
 }
 
 pub async fn download_audio(
     uuid: String,
+    user: Option<User>,
 ) -> std::result::Result<impl warp::Reply, warp::Rejection> {
-    let session_id = crate::session::find_session_with_uuid(&uuid).await.unwrap();
-    let session = crate::session::get_session(&session_id).await.unwrap();
-    let content_path = session.recording_file.unwrap();
+    let session = find_session(&uuid, &user).await?;
+    recording(&session)
+}
+
+fn recording(session: &SessionData) -> std::result::Result<Response<Bytes>, warp::Rejection> {
+    let content_path = session
+        .recording_file
+        .clone()
+        .ok_or_else(|| not_found(format!("Session {} has no recording", session.uuid)))?;
     log::debug!("content_path is {}", content_path);
-    let mut f = std::fs::File::open(content_path.clone()).unwrap();
-    let metadata = std::fs::metadata(&content_path).expect("unable to read metadata");
-    let mut buffer = vec![0; metadata.len() as usize];
-    let _ = f.read(&mut buffer).expect("buffer overflow");
+    let buffer = match std::fs::read(&content_path) {
+        Ok(b) => b,
+        Err(e) => {
+            log::error!("Error reading recording {}: {:?}", content_path, e);
+            return Err(reject(Box::new(e)));
+        }
+    };
     let b: Bytes = Bytes::from(buffer);
     let response = match Response::builder()
         .header(
             "Content-Disposition",
-            format!("attachment; filename=\"{}.wav\"", uuid),
+            format!("attachment; filename=\"{}.wav\"", session.uuid),
         )
         .body(b)
     {
         Ok(b) => b,
         Err(e) => {
             log::error!("Error making response: {:?}", e);
-            return Err(warp::reject::not_found());
+            return Err(warp::reject::custom(ApiError::Internal));
         }
     };
     Ok(response)
 }
 
+/// Makes a read only link to the session, which anyone can use until it
+/// expires.
+pub async fn share_session(
+    uuid: String,
+    request: ShareRequest,
+    user: Option<User>,
+) -> std::result::Result<warp::reply::Response, warp::Rejection> {
+    let session = find_own_session(&uuid, &user).await?;
+    Ok(match share::create(&session.uuid, &request) {
+        Ok(link) => warp::reply::json(&link).into_response(),
+        Err(e) => ApiError::BadRequest(e.to_string()).into_response(),
+    })
+}
+
+/// The session a share token gives access to, and when the token expires.
+async fn shared_session(
+    token: &str,
+) -> std::result::Result<(SessionData, DateTime<Utc>), warp::Rejection> {
+    let not_shared = || not_found("Shared session not found or link expired".to_string());
+    let (uuid, expires_at) = share::verify(token).ok_or_else(not_shared)?;
+    let session_id = find_session_with_uuid(&uuid.to_string())
+        .await
+        .ok_or_else(not_shared)?;
+    let session = get_session(&session_id).await.ok_or_else(not_shared)?;
+    Ok((session, expires_at))
+}
+
+#[derive(Template)]
+#[template(path = "shared.html", escape = "none")]
+pub struct SharedPage {
+    token: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    resource: String,
+    language: String,
+    comparison: bool,
+    dest: String,
+    source: String,
+}
+
+pub async fn shared_page(token: String) -> std::result::Result<impl warp::Reply, warp::Rejection> {
+    let (session, expires_at) = shared_session(&token).await?;
+    let changes = match &session.resource {
+        Some(resource) => {
+            let changes = crate::compare::changes(
+                resource.clone(),
+                session.uuid.to_string(),
+                session.language.clone(),
+                &Normalization::default(),
+            )
+            .await;
+            match changes {
+                Ok(c) => Some(c),
+                Err(e) => {
+                    log::warn!("Couldn't compare shared session {}: {:?}", session.uuid, e);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+    let (dest, source) = match &changes {
+        Some(c) => changes_html(c),
+        None => (
+            escape_html(&session.transcript().unwrap_or_default()).replace('\n', "<br>"),
+            String::new(),
+        ),
+    };
+    let template = SharedPage {
+        token: escape_html(&token),
+        created_at: session.created_at,
+        expires_at,
+        resource: escape_html(session.resource.as_deref().unwrap_or_default()),
+        language: escape_html(&session.language),
+        comparison: changes.is_some(),
+        dest,
+        source,
+    };
+    render(&template)
+}
+
+pub async fn export_session(
+    uuid: String,
+    user: Option<User>,
+) -> std::result::Result<impl warp::Reply, warp::Rejection> {
+    let session = find_session(&uuid, &user).await?;
+    let zip = match crate::export::export_session(&session) {
+        Ok(z) => z,
+        Err(e) => {
+            log::error!("Error exporting session {}: {:?}", uuid, e);
+            return Err(reject(e));
+        }
+    };
+    let response = match Response::builder()
+        .header("Content-Type", "application/zip")
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}.zip\"", uuid),
+        )
+        .body(Bytes::from(zip))
+    {
+        Ok(r) => r,
+        Err(e) => {
+            log::error!("Error making response: {:?}", e);
+            return Err(warp::reject::custom(ApiError::Internal));
+        }
+    };
+    Ok(response)
+}
+
+pub async fn delete_session(
+    uuid: String,
+    user: Option<User>,
+) -> std::result::Result<impl warp::Reply, warp::Rejection> {
+    let session = find_own_session(&uuid, &user).await?;
+    match crate::session::delete_session(session.id, None).await {
+        Ok(_) => Ok("deleted"),
+        Err(e) => {
+            log::error!("Error deleting session {}: {:?}", uuid, e);
+            Err(reject(e))
+        }
+    }
+}
+
+fn list_annotations(session: SessionData) -> std::result::Result<Json, warp::Rejection> {
+    match crate::db::load_annotations(&session.id) {
+        Ok(annotations) => Ok(warp::reply::json(&annotations)),
+        Err(e) => {
+            log::error!("Error loading annotations for {}: {:?}", session.uuid, e);
+            Err(reject(e))
+        }
+    }
+}
+
+/// Peers annotating a session shared with them have to be logged in, so
+/// that only they can change their annotations.
+fn peer(user: Option<User>) -> std::result::Result<Option<User>, warp::Rejection> {
+    match user {
+        Some(user) => Ok(Some(user)),
+        None => Err(warp::reject::custom(ApiError::Unauthorized(
+            "Log in to annotate shared sessions".to_string(),
+        ))),
+    }
+}
+
+fn fluency(session: SessionData) -> warp::reply::Response {
+    match crate::fluency::analyse(&session) {
+        Ok(f) => warp::reply::json(&f).into_response(),
+        Err(e) => {
+            log::error!("Error analysing fluency of {}: {:?}", session.uuid, e);
+            ApiError::from(e).into_response()
+        }
+    }
+}
+
+fn create_annotation(
+    session: SessionData,
+    user: Option<User>,
+    request: AnnotationRequest,
+) -> warp::reply::Response {
+    match annotation::create(&session, &user, &request) {
+        Ok(a) => {
+            warp::reply::with_status(warp::reply::json(&a), StatusCode::CREATED).into_response()
+        }
+        Err(e) => ApiError::BadRequest(e.to_string()).into_response(),
+    }
+}
+
+fn update_annotation(
+    session: SessionData,
+    id: usize,
+    user: Option<User>,
+    request: AnnotationRequest,
+) -> std::result::Result<warp::reply::Response, warp::Rejection> {
+    match annotation::update(&session, &id, &user, &request) {
+        Ok(Some(a)) => Ok(warp::reply::json(&a).into_response()),
+        Ok(None) => Err(not_found(format!("Annotation {} not found", id))),
+        Err(e) => Ok(ApiError::BadRequest(e.to_string()).into_response()),
+    }
+}
+
+fn delete_annotation(
+    session: SessionData,
+    id: usize,
+    user: Option<User>,
+) -> std::result::Result<&'static str, warp::Rejection> {
+    match annotation::delete(&session, &id, &user) {
+        Ok(true) => Ok("deleted"),
+        Ok(false) => Err(not_found(format!("Annotation {} not found", id))),
+        Err(e) => {
+            log::error!("Error deleting annotation {}: {:?}", id, e);
+            Err(reject(e))
+        }
+    }
+}
+
 pub async fn get_resource_filename(resource_path: String) -> E<String> {
     let metadata = match Metadata::from_resource_path(&resource_path) {
         Ok(m) => m,
@@ -136,121 +780,492 @@ pub async fn get_resource_filename(resource_path: String) -> E<String> {
 }
 
 pub async fn serve() {
-    let cors = warp::cors()
-    .allow_any_origin()
-    .allow_methods(vec!["GET", "POST", "PUT", "DELETE"])
-    .allow_headers(vec!["Content-Type", "Authorization"]);
+    let cors = auth::cors();
 
     let chat = warp::path("chat")
         .and(warp::query::<HashMap<String, String>>())
+        .and(auth::authorize(Scope::Create))
         .and(warp::ws())
-        .map(move |params: HashMap<String, String>, ws: warp::ws::Ws| {
-            let lang: String = (params.get("lang").unwrap_or(&"de".to_string())).clone();
-            let resource: Option<String> = params.get("resource").cloned();
-            let sample_rate: u32 = match params.get("rate") {
-                Some(rate) => rate.to_string(),
-                None => "44100".to_string(),
+        .and_then(
+            |params: HashMap<String, String>, user: Option<User>, ws: warp::ws::Ws| async move {
+                let mut lang: String = (params.get("lang").unwrap_or(&"de".to_string())).clone();
+                let mut resource: Option<String> = params.get("resource").cloned();
+                let sample_rate: u32 = match params.get("rate") {
+                    Some(rate) => rate.parse().map_err(|_| {
+                        warp::reject::custom(ApiError::BadRequest(format!(
+                            "Invalid sample rate {}",
+                            rate
+                        )))
+                    })?,
+                    None => 44100,
+                };
+                // Sessions for an assignment are always of its resource and language.
+                let assignment_id = match params.get("assignment") {
+                    Some(id) => {
+                        let id: usize = id.parse().map_err(|_| {
+                            warp::reject::custom(ApiError::BadRequest(format!(
+                                "Invalid assignment {}",
+                                id
+                            )))
+                        })?;
+                        let assignment = match crate::db::load_assignment(&id) {
+                            Ok(Some(a)) => a,
+                            Ok(None) => {
+                                return Err(not_found(format!("Assignment {} not found", id)))
+                            }
+                            Err(e) => {
+                                log::error!("Error loading assignment {}: {:?}", id, e);
+                                return Err(reject(e));
+                            }
+                        };
+                        lang = assignment.lang;
+                        resource = Some(assignment.resource);
+                        Some(id)
+                    }
+                    None => None,
+                };
+                let user_id = user.map(|u| u.id);
+                Ok::<_, warp::Rejection>(ws.on_upgrade(move |socket| {
+                    user_connected(socket, lang, sample_rate, resource, user_id, assignment_id)
+                }))
+            },
+        );
+
+    let close = warp::post()
+        .and(warp::path!("close" / String))
+        .and(auth::authorize(Scope::Create))
+        .and_then(|uuid, user| async move {
+            find_own_session(&uuid, &user).await?;
+            mark_session_for_closure_uuid(uuid).await;
+            Ok::<&str, warp::Rejection>("foo")
+        });
+
+    let star = warp::post()
+        .and(warp::path!("star" / String))
+        .and(auth::authorize(Scope::Create))
+        .and_then(|uuid, user| async move {
+            find_own_session(&uuid, &user).await?;
+            match star_session(uuid, true).await {
+                Ok(_) => Ok("starred"),
+                Err(e) => {
+                    log::error!("Error starring session: {:?}", e);
+                    Err(reject(e))
+                }
             }
-            .parse()
-            .unwrap();
-            ws.on_upgrade(move |socket| user_connected(socket, lang, sample_rate, resource))
         });
 
-    let close = warp::post().and(warp::path!("close" / String).and_then(|uuid| async move {
-        mark_session_for_closure_uuid(uuid).await;
-        Ok::<&str, warp::Rejection>("foo")
-    }));
+    let unstar = warp::post()
+        .and(warp::path!("unstar" / String))
+        .and(auth::authorize(Scope::Create))
+        .and_then(|uuid, user| async move {
+            find_own_session(&uuid, &user).await?;
+            match star_session(uuid, false).await {
+                Ok(_) => Ok("unstarred"),
+                Err(e) => {
+                    log::error!("Error unstarring session: {:?}", e);
+                    Err(reject(e))
+                }
+            }
+        });
+
+    let register = warp::post()
+        .and(warp::path!("register"))
+        .and(form_body())
+        .map(register);
+
+    let login = warp::post()
+        .and(warp::path!("login"))
+        .and(form_body())
+        .map(login);
+
+    let logout = warp::post()
+        .and(warp::path!("logout"))
+        .and(warp::cookie::optional(auth::COOKIE_NAME))
+        .map(logout);
+
+    let create_token = warp::post()
+        .and(warp::path!("tokens"))
+        .and(auth::manages_tokens())
+        .and(json_body())
+        .map(create_token);
+
+    let tokens = warp::get()
+        .and(warp::path!("tokens"))
+        .and(auth::manages_tokens())
+        .and_then(|user| async { list_tokens(user).await });
+
+    let delete_token = warp::delete()
+        .and(warp::path!("tokens" / usize))
+        .and(auth::manages_tokens())
+        .and_then(|id, user| async move { delete_token(id, user).await });
 
     let practice = warp::get().and(
         warp::path!("practice" / String / String)
+            .and(auth::require(Scope::Read))
             .and_then(|directory, lang| async move { practice(directory, lang).await }),
     );
 
     let serve_resource = warp::get().and(
         warp::path!("serve_resource" / String)
+            .and(auth::require(Scope::Read))
             .and(filter_range())
             .and_then(|resource_path: String, range_header| async move {
-                let filename = get_resource_filename(
-                    decode(&resource_path)
-                        .expect("Invalid source path in serve_resource")
-                        .into_owned(),
-                )
-                .await
-                .unwrap();
-                let mime_type = mime_guess::from_path(&filename).first().unwrap();
+                let filename = get_resource_filename(decode_resource(&resource_path)?)
+                    .await
+                    .map_err(|_| not_found(format!("Resource {} not found", resource_path)))?;
+                let mime_type = mime_guess::from_path(&filename).first_or_octet_stream();
                 log::debug!("Found MIME type {}", mime_type.as_ref());
                 get_range(range_header, &filename, mime_type.as_ref()).await
             }),
     );
 
-    let status = warp::path!("status" / String).and_then(|uuid| async move {
-        match crate::session::find_session_with_uuid(&uuid).await {
-            Some(session_id) => match crate::session::get_session(&session_id).await {
-                Some(session) => {
-                    Ok::<Json, warp::Rejection>(warp::reply::json(&session.status().unwrap()))
+    let status = warp::path!("status" / String)
+        .and(auth::authorize(Scope::Read))
+        .and_then(|uuid, user| async move {
+            let session = find_session(&uuid, &user).await?;
+            match session.status() {
+                Ok(status) => Ok::<Json, warp::Rejection>(warp::reply::json(&status)),
+                Err(e) => {
+                    log::error!("Error getting status of {}: {:?}", uuid, e);
+                    Err(reject(e))
                 }
-                None => Err(warp::reject::not_found()),
-            },
-            None => Err(warp::reject::not_found()),
-        }
-    });
+            }
+        });
 
     let compare = warp::get()
         .and(warp::path!("compare" / String / String / String))
-        .and_then(|resource_path: String, uuid, lang| async move {
-            match compare(
-                decode(&resource_path)
-                    .expect("Invalid URL encoding in compare")
-                    .into_owned(),
-                uuid,
-                lang,
-            )
-            .await
-            {
-                Ok(x) => Ok(x),
-                Err(e) => {
-                    log::error!("Error in compare: {:?}", e);
-                    Err(warp::reject())
-                }
-            }
+        .and(auth::authorize(Scope::Read))
+        .and_then(|resource_path: String, uuid, lang, user| async move {
+            compare(decode_resource(&resource_path)?, uuid, lang, user).await
         });
 
     let changes = warp::get()
         .and(warp::path!("changes" / String / String / String))
-        .and_then(|resource_path: String, uuid, lang| async move {
-            match crate::compare::changes(decode(&resource_path).expect("Invlude URL encoding in changes").into_owned(), uuid, lang).await {
-                Ok(x) => {
-                    let changes = x.clone();
-                    let reply = warp::reply::json(&changes);
-                    Ok(reply)
-                }
+        .and(warp::query::<Normalization>())
+        .and(auth::authorize(Scope::Read))
+        .and_then(|resource_path: String, uuid: String, lang, normalization: Normalization, user| async move {
+            find_session(&uuid, &user).await?;
+            let resource_path = decode_resource(&resource_path)?;
+            let changes = crate::compare::changes(resource_path, uuid, lang, &normalization).await;
+            comparison_reply(changes, "changes")
+        });
+
+    let changes_since = warp::get()
+        .and(warp::path!(
+            "changes" / String / String / String / "since" / usize
+        ))
+        .and(warp::query::<Normalization>())
+        .and(auth::authorize(Scope::Read))
+        .and_then(
+            |resource_path: String,
+             uuid: String,
+             lang,
+             since,
+             normalization: Normalization,
+             user| async move {
+                find_session(&uuid, &user).await?;
+                let resource_path = decode_resource(&resource_path)?;
+                let changes =
+                    crate::compare::changes_since(resource_path, uuid, lang, &normalization, since)
+                        .await;
+                comparison_reply(changes, "changes since")
+            },
+        );
+
+    let metrics = warp::get()
+        .and(warp::path!("metrics" / String / String / String))
+        .and(auth::authorize(Scope::Read))
+        .and_then(|resource_path: String, uuid: String, lang, user| async move {
+            find_session(&uuid, &user).await?;
+            let resource_path = decode_resource(&resource_path)?;
+            let metrics = crate::compare::metrics(resource_path, uuid, lang).await;
+            comparison_reply(metrics, "metrics")
+        });
+
+    let alignment = warp::get()
+        .and(warp::path!("alignment" / String / String / String))
+        .and(auth::authorize(Scope::Read))
+        .and_then(|resource_path: String, uuid: String, lang, user| async move {
+            find_session(&uuid, &user).await?;
+            let resource_path = decode_resource(&resource_path)?;
+            let alignment = crate::compare::alignment(resource_path, uuid, lang).await;
+            comparison_reply(alignment, "alignment")
+        });
+
+    let ear_voice_span = warp::get()
+        .and(warp::path!("evs" / String / String / String))
+        .and(warp::query::<TimedQuery>())
+        .and(auth::authorize(Scope::Read))
+        .and_then(
+            |resource_path: String, uuid: String, lang, query: TimedQuery, user| async move {
+                find_session(&uuid, &user).await?;
+                let resource_path = decode_resource(&resource_path)?;
+                let max_lag = max_lag(&query)?;
+                let span = crate::compare::ear_voice_span(resource_path, uuid, lang, max_lag).await;
+                comparison_reply(span, "ear-voice span")
+            },
+        );
+
+    let entities = warp::get()
+        .and(warp::path!("entities" / String / String / String))
+        .and(auth::authorize(Scope::Read))
+        .and_then(|resource_path: String, uuid: String, lang, user| async move {
+            find_session(&uuid, &user).await?;
+            let resource_path = decode_resource(&resource_path)?;
+            let entities = crate::compare::entities(resource_path, uuid, lang).await;
+            comparison_reply(entities, "numbers and names")
+        });
+
+    let references = warp::get()
+        .and(warp::path!("references" / String / String / String))
+        .and(auth::authorize(Scope::Read))
+        .and_then(|resource_path: String, uuid: String, lang, user| async move {
+            find_session(&uuid, &user).await?;
+            let resource_path = decode_resource(&resource_path)?;
+            let comparison = crate::compare::references(resource_path, uuid, lang).await;
+            comparison_reply(comparison, "references")
+        });
+
+    let terminology = warp::get()
+        .and(warp::path!("terminology" / String / String / String))
+        .and(auth::authorize(Scope::Read))
+        .and_then(|resource_path: String, uuid: String, lang, user| async move {
+            find_session(&uuid, &user).await?;
+            let resource_path = decode_resource(&resource_path)?;
+            let terminology = crate::compare::terminology(resource_path, uuid, lang).await;
+            comparison_reply(terminology, "terminology")
+        });
+
+    let timed = warp::get()
+        .and(warp::path!("timed" / String / String / String))
+        .and(warp::query::<TimedQuery>())
+        .and(auth::authorize(Scope::Read))
+        .and_then(
+            |resource_path: String, uuid: String, lang, query: TimedQuery, user| async move {
+                find_session(&uuid, &user).await?;
+                let resource_path = decode_resource(&resource_path)?;
+                let max_lag = max_lag(&query)?;
+                let timed = crate::compare::timed(resource_path, uuid, lang, max_lag).await;
+                comparison_reply(timed, "timed comparison")
+            },
+        );
+
+    let delete_session = warp::delete()
+        .and(warp::path!("session" / String))
+        .and(auth::authorize(Scope::Create))
+        .and_then(|uuid, user| async { delete_session(uuid, user).await });
+
+    let events = warp::get()
+        .and(warp::path!("events" / String))
+        .and(auth::authorize(Scope::Read))
+        .and_then(|uuid, user| async move {
+            let session = find_session(&uuid, &user).await?;
+            match crate::events::follow(&session).await {
+                Ok(stream) => Ok(warp::sse::reply(warp::sse::keep_alive().stream(stream))),
                 Err(e) => {
-                    log::error!("Error in changes: {:?}", e);
-                    Err(warp::reject())
+                    log::error!("Error following session {}: {:?}", uuid, e);
+                    Err(reject(e))
                 }
             }
         });
 
+    let export = warp::get()
+        .and(warp::path!("session" / String / "export"))
+        .and(auth::authorize(Scope::Read))
+        .and_then(|uuid, user| async { export_session(uuid, user).await });
+
+    let annotations = warp::get()
+        .and(warp::path!("session" / String / "annotations"))
+        .and(auth::authorize(Scope::Read))
+        .and_then(|uuid, user| async move {
+            let session = find_session(&uuid, &user).await?;
+            list_annotations(session)
+        });
+
+    let fluency = warp::get()
+        .and(warp::path!("session" / String / "fluency"))
+        .and(auth::authorize(Scope::Read))
+        .and_then(|uuid: String, user| async move {
+            let session = find_session(&uuid, &user).await?;
+            Ok::<_, warp::Rejection>(fluency(session))
+        });
+
+    let shared_annotations = warp::get()
+        .and(warp::path!("shared" / String / "annotations"))
+        .and_then(|token: String| async move {
+            let (session, _) = shared_session(&token).await?;
+            list_annotations(session)
+        });
+
+    let shared_create_annotation = warp::post()
+        .and(warp::path!("shared" / String / "annotations"))
+        .and(auth::authorize(Scope::Create))
+        .and(json_body())
+        .and_then(|token: String, user, request| async move {
+            let user = peer(user)?;
+            let (session, _) = shared_session(&token).await?;
+            Ok::<_, warp::Rejection>(create_annotation(session, user, request))
+        });
+
+    let shared_update_annotation = warp::put()
+        .and(warp::path!("shared" / String / "annotations" / usize))
+        .and(auth::authorize(Scope::Create))
+        .and(json_body())
+        .and_then(|token: String, id, user, request| async move {
+            let user = peer(user)?;
+            let (session, _) = shared_session(&token).await?;
+            update_annotation(session, id, user, request)
+        });
+
+    let shared_delete_annotation = warp::delete()
+        .and(warp::path!("shared" / String / "annotations" / usize))
+        .and(auth::authorize(Scope::Create))
+        .and_then(|token: String, id, user| async move {
+            let user = peer(user)?;
+            let (session, _) = shared_session(&token).await?;
+            delete_annotation(session, id, user)
+        });
+
+    let create_annotation = warp::post()
+        .and(warp::path!("session" / String / "annotations"))
+        .and(auth::authorize(Scope::Create))
+        .and(json_body())
+        .and_then(|uuid, user, request| async move {
+            let session = find_session(&uuid, &user).await?;
+            Ok::<_, warp::Rejection>(create_annotation(session, user, request))
+        });
+
+    let update_annotation = warp::put()
+        .and(warp::path!("session" / String / "annotations" / usize))
+        .and(auth::authorize(Scope::Create))
+        .and(json_body())
+        .and_then(|uuid, id, user, request| async move {
+            let session = find_session(&uuid, &user).await?;
+            update_annotation(session, id, user, request)
+        });
+
+    let delete_annotation = warp::delete()
+        .and(warp::path!("session" / String / "annotations" / usize))
+        .and(auth::authorize(Scope::Create))
+        .and_then(|uuid, id, user| async move {
+            let session = find_session(&uuid, &user).await?;
+            delete_annotation(session, id, user)
+        });
+
+    let share = warp::post()
+        .and(warp::path!("session" / String / "share"))
+        .and(warp::query::<ShareRequest>())
+        .and(auth::authorize(Scope::Create))
+        .and_then(|uuid, request, user| async { share_session(uuid, request, user).await });
+
+    let shared = warp::get()
+        .and(warp::path!("shared" / String))
+        .and_then(|token| async { shared_page(token).await });
+
+    let shared_recording = warp::get()
+        .and(warp::path!("shared" / String / "recording"))
+        .and_then(|token: String| async move {
+            let (session, _) = shared_session(&token).await?;
+            recording(&session)
+        });
+
+    let shared_transcript = warp::get()
+        .and(warp::path!("shared" / String / "transcript"))
+        .and_then(|token: String| async move {
+            let (session, _) = shared_session(&token).await?;
+            session.transcript().map_err(reject)
+        });
+
+    let shared_changes = warp::get()
+        .and(warp::path!("shared" / String / "changes"))
+        .and_then(|token: String| async move {
+            let (session, _) = shared_session(&token).await?;
+            let resource = session
+                .resource
+                .clone()
+                .ok_or_else(|| not_found("Shared session has no resource".to_string()))?;
+            let changes = crate::compare::changes(
+                resource,
+                session.uuid.to_string(),
+                session.language,
+                &Normalization::default(),
+            )
+            .await;
+            comparison_reply(changes, "shared changes")
+        });
+
+    let shared_metrics = warp::get()
+        .and(warp::path!("shared" / String / "metrics"))
+        .and_then(|token: String| async move {
+            let (session, _) = shared_session(&token).await?;
+            let resource = session
+                .resource
+                .clone()
+                .ok_or_else(|| not_found("Shared session has no resource".to_string()))?;
+            let metrics =
+                crate::compare::metrics(resource, session.uuid.to_string(), session.language).await;
+            comparison_reply(metrics, "shared metrics")
+        });
+
+    let sessions = warp::get()
+        .and(warp::path!("sessions"))
+        .and(warp::query::<SessionQuery>())
+        .and(auth::authorize(Scope::Read))
+        .and_then(|query, user| async { list_sessions(query, user).await });
+
     let recording = warp::get()
         .and(warp::path!("recording" / String))
-        .and_then(|uuid| async { download_audio(uuid).await });
+        .and(auth::authorize(Scope::Read))
+        .and_then(|uuid, user| async { download_audio(uuid, user).await });
+
+    let create_assignment = warp::post()
+        .and(warp::path!("assignments"))
+        .and(auth::authorize(Scope::Teach))
+        .and(json_body())
+        .map(create_assignment);
+
+    let assignments = warp::get()
+        .and(warp::path!("assignments"))
+        .and(auth::authorize(Scope::Read))
+        .and_then(|user| async { list_assignments(user).await });
+
+    let assignment = warp::get()
+        .and(warp::path!("assignments" / usize))
+        .and(warp::query::<SubmissionQuery>())
+        .and(auth::authorize(Scope::Teach))
+        .and_then(|id, query, user| async move { assignment_page(id, query, user).await });
+
+    let submissions = warp::get()
+        .and(warp::path!("assignments" / usize / "submissions"))
+        .and(warp::query::<SubmissionQuery>())
+        .and(auth::authorize(Scope::Teach))
+        .and_then(|id, query, user| async move {
+            let (_, submissions) = assignment_submissions(id, query, user).await?;
+            Ok::<Json, warp::Rejection>(warp::reply::json(&submissions))
+        });
+
+    let summary = warp::get()
+        .and(warp::path!("assignments" / usize / "summary.csv"))
+        .and(warp::query::<SubmissionQuery>())
+        .and(auth::authorize(Scope::Teach))
+        .and_then(|id, query, user| async move { assignment_summary(id, query, user).await });
 
     let assets_dir = std::env::var("ASSETS_DIR").unwrap_or("../assets".to_string());
     let assets = warp::get()
         .and(warp::path("assets"))
+        .and(auth::require(Scope::Read))
         .and(warp::fs::dir(assets_dir));
 
-    let transcript = warp::path!("transcript" / String).and_then(|uuid| async move {
-        match crate::session::find_session_with_uuid(&uuid).await {
-            Some(session_id) => match crate::session::get_session(&session_id).await {
-                Some(session) => Ok(session.transcript().unwrap()),
-                None => Err(warp::reject::not_found()),
-            },
-            None => Err(warp::reject::not_found()),
-        }
-    });
+    let transcript = warp::path!("transcript" / String)
+        .and(auth::authorize(Scope::Read))
+        .and_then(|uuid, user| async move {
+            let session = find_session(&uuid, &user).await?;
+            session.transcript().map_err(reject)
+        });
 
-    let index = warp::path::end().and_then(|| async move { crate::api::index().await });
+    let index = warp::path::end()
+        .and(auth::current_user())
+        .and_then(|user| async move { crate::api::index(user).await });
 
     #[derive(RustEmbed)]
     #[folder = "../client"]
@@ -258,17 +1273,58 @@ pub async fn serve() {
     let static_content_serve = warp_embed::embed(&StaticContent);
 
     let routes = index
+        .or(alignment)
+        .or(annotations)
         .or(assets)
+        .or(assignment)
+        .or(assignments)
         .or(changes)
+        .or(changes_since)
         .or(chat)
         .or(close)
         .or(compare)
+        .or(create_annotation)
+        .or(create_assignment)
+        .or(create_token)
+        .or(delete_annotation)
+        .or(delete_session)
+        .or(delete_token)
+        .or(ear_voice_span)
+        .or(entities)
+        .or(events)
+        .or(export)
+        .or(fluency)
+        .or(login)
+        .or(logout)
+        .or(metrics)
         .or(practice)
         .or(recording)
+        .or(references)
+        .or(register)
         .or(serve_resource)
+        .or(sessions)
+        .or(share)
+        .or(shared)
+        .or(shared_annotations)
+        .or(shared_changes)
+        .or(shared_create_annotation)
+        .or(shared_delete_annotation)
+        .or(shared_metrics)
+        .or(shared_recording)
+        .or(shared_transcript)
+        .or(shared_update_annotation)
+        .or(star)
         .or(status)
         .or(static_content_serve)
+        .or(submissions)
+        .or(summary)
+        .or(terminology)
+        .or(timed)
+        .or(tokens)
         .or(transcript)
+        .or(unstar)
+        .or(update_annotation)
+        .recover(crate::error::handle_rejection)
         .with(cors);
     log::debug!("Starting server");
     let listen;
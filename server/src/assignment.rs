@@ -0,0 +1,232 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::auth::{Scope, User};
+use crate::compare::Change;
+use crate::db;
+use crate::error::{Er, E};
+use crate::metadata::Metadata;
+use crate::metrics::Metrics;
+use crate::normalize::Normalization;
+use crate::session::{SessionData, SessionState};
+
+/// A resource bundle to be interpreted into a language by a deadline, set
+/// by a teacher. Students record sessions for it by passing its id as the
+/// `assignment` parameter to `/chat`.
+#[derive(Clone, Debug, Serialize)]
+pub struct Assignment {
+    pub id: usize,
+    pub teacher_id: usize,
+    pub name: String,
+    pub resource: String,
+    pub lang: String,
+    pub deadline: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct NewAssignment {
+    pub name: String,
+    pub resource: String,
+    pub lang: String,
+    pub deadline: DateTime<Utc>,
+}
+
+/// Word counts of a comparison. `equal` words are in both the transcript
+/// and the reference, `inserted` words only in the reference and `deleted`
+/// words only in the transcript.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ChangeCounts {
+    pub equal: usize,
+    pub inserted: usize,
+    pub deleted: usize,
+}
+
+impl ChangeCounts {
+    pub fn from_changes(changes: &[Change]) -> Self {
+        let mut counts = Self::default();
+        for change in changes.iter().filter(|c| !c.content.trim().is_empty()) {
+            match change.change_type.as_str() {
+                "equal" => counts.equal += 1,
+                "insert" => counts.inserted += 1,
+                "delete" => counts.deleted += 1,
+                _ => (),
+            }
+        }
+        counts
+    }
+
+    /// The proportion of the reference which is in the transcript.
+    pub fn coverage(&self) -> f64 {
+        if self.equal + self.inserted == 0 {
+            return 0.0;
+        }
+        self.equal as f64 / (self.equal + self.inserted) as f64
+    }
+}
+
+/// A student's session for an assignment, compared with the reference.
+#[derive(Clone, Serialize)]
+pub struct Submission {
+    pub uuid: Uuid,
+    pub student: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub late: bool,
+    pub state: SessionState,
+    pub counts: ChangeCounts,
+    pub metrics: Metrics,
+    pub changes: Vec<Change>,
+}
+
+/// What to order submissions by. Error rates sort lowest first, scores
+/// highest first.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionSort {
+    #[default]
+    CreatedAt,
+    Wer,
+    Bleu,
+    Chrf,
+    Recall,
+}
+
+#[derive(Default, Deserialize)]
+pub struct SubmissionQuery {
+    #[serde(default)]
+    pub sort: SubmissionSort,
+}
+
+pub fn sort_submissions(submissions: &mut [Submission], sort: SubmissionSort) {
+    let key = |s: &Submission| match sort {
+        SubmissionSort::CreatedAt => s.created_at.timestamp() as f64,
+        SubmissionSort::Wer => s.metrics.wer,
+        SubmissionSort::Bleu => -s.metrics.bleu,
+        SubmissionSort::Chrf => -s.metrics.chrf,
+        SubmissionSort::Recall => -s.metrics.recall,
+    };
+    submissions.sort_by(|a, b| key(a).total_cmp(&key(b)));
+}
+
+impl Assignment {
+    /// Whether `user` set this assignment, or is an admin.
+    pub fn is_teacher(&self, user: &Option<User>) -> bool {
+        match user {
+            Some(u) => u.is_admin() || (u.has_scope(Scope::Teach) && u.id == self.teacher_id),
+            None => false,
+        }
+    }
+}
+
+pub fn create(teacher: &User, new: &NewAssignment) -> E<Assignment> {
+    let metadata = Metadata::from_resource_path(&new.resource)?;
+    if !metadata.has_translation(&new.lang) {
+        return Err(Er::new(format!(
+            "Resource {} has no translation for {}",
+            new.resource, new.lang
+        )));
+    }
+    let id = db::insert_assignment(&teacher.id, new)?;
+    log::info!("User {} created assignment {}", teacher.username, id);
+    db::load_assignment(&id)?.ok_or(Er::new(format!("Assignment {} not found", id)))
+}
+
+/// Whether `user` set the assignment `session` was recorded for.
+pub fn teaches(user: &Option<User>, session: &SessionData) -> bool {
+    let assignment_id = match session.assignment_id {
+        Some(id) => id,
+        None => return false,
+    };
+    match db::load_assignment(&assignment_id) {
+        Ok(Some(assignment)) => assignment.is_teacher(user),
+        Ok(None) => false,
+        Err(e) => {
+            log::error!("Error loading assignment {}: {:?}", assignment_id, e);
+            false
+        }
+    }
+}
+
+pub async fn submissions(assignment: &Assignment) -> E<Vec<Submission>> {
+    let sessions = db::load_assignment_sessions(&assignment.id)?;
+    let mut submissions = vec![];
+    for (session, student) in sessions {
+        let changes = match crate::compare::changes(
+            assignment.resource.clone(),
+            session.uuid.to_string(),
+            assignment.lang.clone(),
+            &Normalization::default(),
+        )
+        .await
+        {
+            Ok(c) => c,
+            Err(e) => {
+                log::warn!("Couldn't compare session {}: {:?}", session.uuid, e);
+                vec![]
+            }
+        };
+        let metrics = match crate::compare::metrics(
+            assignment.resource.clone(),
+            session.uuid.to_string(),
+            assignment.lang.clone(),
+        )
+        .await
+        {
+            Ok(m) => m,
+            Err(e) => {
+                log::warn!("Couldn't score session {}: {:?}", session.uuid, e);
+                Metrics::default()
+            }
+        };
+        submissions.push(Submission {
+            uuid: session.uuid,
+            student,
+            created_at: session.created_at,
+            late: session.created_at > assignment.deadline,
+            state: session.state,
+            counts: ChangeCounts::from_changes(&changes),
+            metrics,
+            changes,
+        });
+    }
+    Ok(submissions)
+}
+
+/// One line per submission, for spreadsheets.
+pub fn summary_csv(submissions: &[Submission]) -> String {
+    let mut csv = String::from(
+        "student,session,created_at,late,state,equal_words,inserted_words,deleted_words,coverage,\
+         wer,precision,recall,chrf,bleu,omissions,additions\n",
+    );
+    for submission in submissions {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{},{}\n",
+            csv_field(submission.student.as_deref().unwrap_or("anonymous")),
+            submission.uuid,
+            submission.created_at.to_rfc3339(),
+            submission.late,
+            submission.state.as_str(),
+            submission.counts.equal,
+            submission.counts.inserted,
+            submission.counts.deleted,
+            submission.counts.coverage(),
+            submission.metrics.wer,
+            submission.metrics.precision,
+            submission.metrics.recall,
+            submission.metrics.chrf,
+            submission.metrics.bleu,
+            submission.metrics.omissions,
+            submission.metrics.additions,
+        ));
+    }
+    csv
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,365 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use warp::{Filter, Rejection};
+
+use crate::db;
+use crate::error::{ApiError, Er, E};
+
+pub const COOKIE_NAME: &str = "terplounge_login";
+const LOGIN_DAYS: i64 = 30;
+
+/// What a user, or an API token, may do.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// See one's own sessions, their transcripts and comparisons.
+    Read,
+    /// Record new sessions, and close, star or delete one's own sessions.
+    Create,
+    /// Set assignments and see the sessions recorded for them.
+    Teach,
+    /// Everything, for every user's sessions.
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Create => "create",
+            Scope::Teach => "teach",
+            Scope::Admin => "admin",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "read" => Some(Scope::Read),
+            "create" => Some(Scope::Create),
+            "teach" => Some(Scope::Teach),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct User {
+    pub id: usize,
+    pub username: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl User {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.is_admin()
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.scopes.contains(&Scope::Admin)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+/// An API token as listed to its owner. The token itself is only shown
+/// once, when it is created.
+#[derive(Serialize)]
+pub struct ApiToken {
+    pub id: usize,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// When `LOGIN_REQUIRED` is set, anonymous users can't record or see
+/// sessions. Otherwise accounts are optional, and sessions made without
+/// logging in are visible to everyone who isn't logged in.
+pub fn login_required() -> bool {
+    std::env::var("LOGIN_REQUIRED").is_ok_and(|x| x == "true" || x == "1")
+}
+
+fn listed(var: &str, username: &str) -> bool {
+    std::env::var(var)
+        .map(|users| users.split(',').any(|user| user.trim() == username))
+        .unwrap_or(false)
+}
+
+/// The scopes a user logged in with a password has. Users listed in
+/// `ADMIN_USERS` are admins, and those in `TEACHERS` are teachers, both
+/// separated by commas.
+pub fn login_scopes(username: &str) -> Vec<Scope> {
+    let mut scopes = vec![Scope::Read, Scope::Create];
+    if listed("TEACHERS", username) {
+        scopes.push(Scope::Teach);
+    }
+    if listed("ADMIN_USERS", username) {
+        scopes.push(Scope::Admin);
+    }
+    scopes
+}
+
+/// Registers a user through the API. Teachers and admins can't register
+/// themselves, or anyone could take their name first; their accounts are
+/// made on the server with `create-user`.
+pub fn register(credentials: &Credentials) -> E<User> {
+    if listed("TEACHERS", &credentials.username) || listed("ADMIN_USERS", &credentials.username) {
+        return Err(Er::new(format!(
+            "User {} must be created by the administrator",
+            credentials.username
+        )));
+    }
+    create_user(credentials)
+}
+
+pub fn create_user(credentials: &Credentials) -> E<User> {
+    if credentials.username.is_empty() || credentials.password.len() < 8 {
+        return Err(Er::new(
+            "Username must not be empty and password must be at least 8 characters".to_string(),
+        ));
+    }
+    if db::find_user(&credentials.username)?.is_some() {
+        return Err(Er::new(format!(
+            "User {} already exists",
+            credentials.username
+        )));
+    }
+    let salt = SaltString::encode_b64(Uuid::new_v4().as_bytes())?;
+    let hash = Argon2::default()
+        .hash_password(credentials.password.as_bytes(), &salt)?
+        .to_string();
+    let id = db::insert_user(&credentials.username, &hash)?;
+    Ok(User {
+        id,
+        username: credentials.username.clone(),
+        scopes: login_scopes(&credentials.username),
+    })
+}
+
+/// Checks the password and returns a token to be sent back in the login
+/// cookie.
+pub fn login(credentials: &Credentials) -> E<String> {
+    let (user, hash) = match db::find_user(&credentials.username)? {
+        Some(x) => x,
+        None => return Err(Er::new("Wrong username or password".to_string())),
+    };
+    let hash = PasswordHash::new(&hash)?;
+    if Argon2::default()
+        .verify_password(credentials.password.as_bytes(), &hash)
+        .is_err()
+    {
+        return Err(Er::new("Wrong username or password".to_string()));
+    }
+    let token = Uuid::new_v4().simple().to_string();
+    db::insert_login(&token, &user.id, Utc::now() + Duration::days(LOGIN_DAYS))?;
+    log::debug!("User {} logged in", user.username);
+    Ok(token)
+}
+
+pub fn logout(token: &str) -> E<()> {
+    db::delete_login(token)
+}
+
+/// Only the hash of API tokens is stored.
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Makes a new API token for `user`, returning the token. Tokens can't
+/// have scopes their creator doesn't have.
+pub fn create_token(user: &User, request: &TokenRequest) -> E<String> {
+    if let Some(scope) = request.scopes.iter().find(|s| !user.has_scope(**s)) {
+        return Err(Er::new(format!(
+            "User {} can't grant scope {}",
+            user.username,
+            scope.as_str()
+        )));
+    }
+    let token = format!("tpl_{}", Uuid::new_v4().simple());
+    db::insert_api_token(
+        &hash_token(&token),
+        &user.id,
+        &request.name,
+        &request.scopes,
+    )?;
+    log::info!("User {} created API token {}", user.username, request.name);
+    Ok(token)
+}
+
+pub fn login_cookie(token: &str) -> String {
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}",
+        COOKIE_NAME,
+        token,
+        LOGIN_DAYS * 86400
+    )
+}
+
+pub fn logout_cookie() -> String {
+    format!(
+        "{}=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0",
+        COOKIE_NAME
+    )
+}
+
+/// Rejection for requests which need a logged in user and don't have one.
+fn unauthorized() -> Rejection {
+    warp::reject::custom(ApiError::Unauthorized("Login required".to_string()))
+}
+
+/// How the user of a request identified themselves.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Credential {
+    ApiToken,
+    Login,
+}
+
+/// The user identified by an `Authorization: Bearer` token or the login
+/// cookie, if any, and which it was. A bearer token which isn't valid
+/// rejects the request.
+fn identify() -> impl Filter<Extract = (Option<(User, Credential)>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::cookie::optional::<String>(COOKIE_NAME))
+        .and_then(
+            |authorization: Option<String>, cookie: Option<String>| async move {
+                if let Some(authorization) = authorization {
+                    let token = authorization
+                        .strip_prefix("Bearer ")
+                        .ok_or(unauthorized())?;
+                    return match db::find_api_token(&hash_token(token)) {
+                        Ok(Some(user)) => Ok(Some((user, Credential::ApiToken))),
+                        Ok(None) => Err(unauthorized()),
+                        Err(e) => {
+                            log::error!("Error looking up API token: {:?}", e);
+                            Err(unauthorized())
+                        }
+                    };
+                }
+                let token = match cookie {
+                    Some(t) => t,
+                    None => return Ok(None),
+                };
+                match db::find_login(&token) {
+                    Ok(user) => Ok(user.map(|u| (u, Credential::Login))),
+                    Err(e) => {
+                        log::error!("Error looking up login: {:?}", e);
+                        Ok(None)
+                    }
+                }
+            },
+        )
+}
+
+/// The user identified by an `Authorization: Bearer` token or the login
+/// cookie, if any. A bearer token which isn't valid rejects the request.
+pub fn current_user() -> impl Filter<Extract = (Option<User>,), Error = Rejection> + Clone {
+    identify().map(|user: Option<(User, Credential)>| user.map(|(user, _)| user))
+}
+
+/// The current user, if they have `scope`. Anonymous users have the read
+/// and create scopes, unless `LOGIN_REQUIRED` is set.
+pub fn authorize(
+    scope: Scope,
+) -> impl Filter<Extract = (Option<User>,), Error = Rejection> + Clone {
+    current_user().and_then(move |user: Option<User>| async move {
+        match &user {
+            Some(u) if !u.has_scope(scope) => Err(warp::reject::custom(ApiError::Forbidden(
+                "Not allowed".to_string(),
+            ))),
+            None if login_required() || scope == Scope::Admin => Err(unauthorized()),
+            _ => Ok(user),
+        }
+    })
+}
+
+/// Like `authorize`, for routes which don't care who the user is.
+pub fn require(scope: Scope) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    authorize(scope).map(|_| ()).untuple_one()
+}
+
+/// The current user, for managing their API tokens. That takes logging in
+/// with a password, or a token with the admin scope, so that a token which
+/// leaks can't be used to make others or to revoke its owner's.
+pub fn manages_tokens() -> impl Filter<Extract = (User,), Error = Rejection> + Clone {
+    identify().and_then(|user: Option<(User, Credential)>| async move {
+        match user {
+            Some((user, Credential::Login)) => Ok(user),
+            Some((user, Credential::ApiToken)) if user.is_admin() => Ok(user),
+            Some(_) => Err(warp::reject::custom(ApiError::Forbidden(
+                "API tokens can only be managed after logging in".to_string(),
+            ))),
+            None => Err(unauthorized()),
+        }
+    })
+}
+
+/// Whether `origin` is a scheme and host, with an optional port, like
+/// `https://example.com:8443`, and nothing else.
+fn is_origin(origin: &str) -> bool {
+    match origin.parse::<warp::http::Uri>() {
+        Ok(uri) => match (uri.scheme_str(), uri.authority()) {
+            (Some(scheme), Some(authority)) => {
+                format!("{}://{}", scheme, authority) == origin && !authority.as_str().contains('@')
+            }
+            _ => false,
+        },
+        Err(_) => false,
+    }
+}
+
+/// Allowed origins for cross-origin requests are set in
+/// `CORS_ALLOWED_ORIGINS`, separated by commas. If it isn't set any origin is
+/// allowed. Entries which aren't origins are logged and left out.
+pub fn cors() -> warp::cors::Builder {
+    let cors = warp::cors()
+        .allow_methods(vec!["GET", "POST", "PUT", "DELETE"])
+        .allow_headers(vec!["Content-Type", "Authorization"]);
+    match std::env::var("CORS_ALLOWED_ORIGINS") {
+        Ok(origins) => cors.allow_origins(
+            origins
+                .split(',')
+                .map(|o| o.trim())
+                .filter(|o| !o.is_empty())
+                .filter(|o| {
+                    let valid = is_origin(o);
+                    if !valid {
+                        log::error!("Ignoring invalid origin {} in CORS_ALLOWED_ORIGINS", o);
+                    }
+                    valid
+                })
+                .collect::<Vec<&str>>(),
+        ),
+        Err(_) => cors.allow_any_origin(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origins() {
+        assert!(is_origin("https://example.com"));
+        assert!(is_origin("http://localhost:3000"));
+        assert!(!is_origin("example.com"));
+        assert!(!is_origin("https://example.com/"));
+        assert!(!is_origin("https://example.com/lms"));
+        assert!(!is_origin("https://user@example.com"));
+        assert!(!is_origin("https://exa mple.com"));
+        assert!(!is_origin("*"));
+    }
+}
@@ -1,25 +1,137 @@
-use crate::error::{Er, E};
+use crate::assignment::ChangeCounts;
+use crate::entities::{self, EntityAccuracy};
+use crate::error::E;
+use crate::glossary::{self, Terminology};
 use crate::metadata::Metadata;
-use crate::session::find_session_with_uuid;
-use serde::Serialize;
+use crate::metrics::Metrics;
+use crate::normalize::Normalization;
+use crate::session::{find_session_with_uuid, SessionData};
+use crate::timed::{self, EarVoiceSpan, TimedComparison};
+use crate::translate::TranslationResponses;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use similar::{ChangeTag, TextDiff};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::time::Instant;
 
-fn get_translation(resource_path: &String, lang: &String) -> E<String> {
-    let metadata = Metadata::from_resource_path(resource_path)?;
-    let source_path = format!(
-        "{}/{}",
-        metadata.enclosing_directory,
-        metadata.translations.get(lang).unwrap_or_else(|| {
-            panic!(
-                "Translation not found for resource {} and lang {}",
-                resource_path, lang
-            );
-        })
-    );
-    let source = fs::read_to_string(source_path.clone())?;
-    Ok(source)
+/// Why a comparison can't be made. Unlike other errors these are the
+/// client's doing, and are reported to it.
+#[derive(Debug, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum CompareError {
+    UnknownResource {
+        resource: String,
+    },
+    /// The resource has no reference translation into the language.
+    MissingTranslation {
+        resource: String,
+        lang: String,
+        available: Vec<String>,
+    },
+    UnknownSession {
+        uuid: String,
+    },
+    MissingGlossary {
+        resource: String,
+    },
+}
+
+impl std::fmt::Display for CompareError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CompareError::UnknownResource { resource } => {
+                write!(f, "Resource {} not found", resource)
+            }
+            CompareError::MissingTranslation {
+                resource,
+                lang,
+                available,
+            } => write!(
+                f,
+                "Resource {} has no translation for {}, only for {}",
+                resource,
+                lang,
+                available.join(", ")
+            ),
+            CompareError::UnknownSession { uuid } => write!(f, "Session {} not found", uuid),
+            CompareError::MissingGlossary { resource } => {
+                write!(f, "Resource {} has no glossary", resource)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompareError {}
+
+impl warp::reject::Reject for CompareError {}
+
+impl CompareError {
+    pub fn missing_translation(metadata: &Metadata, resource_path: &str, lang: &str) -> Self {
+        let mut available: Vec<String> = metadata
+            .translations
+            .keys()
+            .chain(metadata.timed_translations.keys())
+            .cloned()
+            .collect();
+        available.sort();
+        available.dedup();
+        CompareError::MissingTranslation {
+            resource: resource_path.to_string(),
+            lang: lang.to_string(),
+            available,
+        }
+    }
+}
+
+fn load_metadata(resource_path: &String) -> E<Metadata> {
+    match Metadata::from_resource_path(resource_path) {
+        Ok(m) => Ok(m),
+        Err(e) => {
+            log::debug!("Couldn't load resource {}: {:?}", resource_path, e);
+            Err(Box::new(CompareError::UnknownResource {
+                resource: resource_path.clone(),
+            }))
+        }
+    }
+}
+
+/// The texts of the reference translations of a resource into `lang`. The
+/// first is the one compared with when only one is used.
+fn get_references(resource_path: &String, lang: &String) -> E<Vec<String>> {
+    let metadata = load_metadata(resource_path)?;
+    if !metadata.translations.contains_key(lang) && metadata.timed_translations.contains_key(lang) {
+        return Ok(vec![timed::reference_text(&timed::load(
+            &metadata,
+            resource_path,
+            lang,
+        )?)]);
+    }
+    let files = match metadata.translations.get(lang) {
+        Some(f) if !f.files().is_empty() => f.files(),
+        _ => {
+            return Err(Box::new(CompareError::missing_translation(
+                &metadata,
+                resource_path,
+                lang,
+            )))
+        }
+    };
+    let mut references = vec![];
+    for file in files {
+        references.push(fs::read_to_string(format!(
+            "{}/{}",
+            metadata.enclosing_directory, file
+        ))?);
+    }
+    Ok(references)
+}
+
+pub fn get_translation(resource_path: &String, lang: &String) -> E<String> {
+    Ok(get_references(resource_path, lang)?.remove(0))
 }
 
 pub struct Comparison {
@@ -36,29 +148,120 @@ pub async fn get_comparison(resource_path: &str, uuid: &str, lang: &str) -> E<Co
     })
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Change {
     pub change_type: String,
     pub content: String,
 }
 
-pub async fn changes(resource_path: String, uuid: String, lang: String) -> E<Vec<Change>> {
-    let source = get_translation(&resource_path, &lang)?;
-    let session_id = find_session_with_uuid(&uuid)
-        .await
-        .expect("Session not found");
-
-    let session = match crate::session::get_session(&session_id).await {
-        Some(s) => s,
-        None => return Err(Er::new(format!("Session {} not found", session_id))),
+async fn find_session(uuid: &String) -> E<SessionData> {
+    let session_id = match find_session_with_uuid(uuid).await {
+        Some(id) => id,
+        None => {
+            return Err(Box::new(CompareError::UnknownSession {
+                uuid: uuid.clone(),
+            }))
+        }
     };
 
-    let dest = session.transcript()?;
+    match crate::session::get_session(&session_id).await {
+        Some(s) => Ok(s),
+        None => Err(Box::new(CompareError::UnknownSession {
+            uuid: uuid.clone(),
+        })),
+    }
+}
+
+/// The session with the given uuid, and the reference translation it is
+/// compared with.
+async fn session_and_reference(
+    resource_path: &String,
+    uuid: &String,
+    lang: &String,
+) -> E<(SessionData, String)> {
+    let source = get_translation(resource_path, lang)?;
+    let session = find_session(uuid).await?;
+    Ok((session, source))
+}
 
-    log::debug!("Comparing");
+/// Comparisons kept between requests for clients following a session as it
+/// is recorded, so that one isn't made again until the transcript changes,
+/// and then only its end is compared again. Splicing the end onto the start
+/// isn't always what comparing the whole texts again would give, so these
+/// are only used for changes since a sequence.
+#[derive(Clone)]
+struct CachedChanges {
+    translation_count: usize,
+    segment_count: usize,
+    /// How many sequences the transcript had.
+    sequence_count: usize,
+    /// The reference and transcript, normalized.
+    reference: String,
+    transcript: String,
+    changes: Vec<Change>,
+    /// For each last sequence the comparison has had, the index of the
+    /// first change which is different from when it was the last one, so
+    /// clients which saw the comparison then know what to ask for again.
+    changed_since: BTreeMap<usize, usize>,
+    used: Instant,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct ChangesKey {
+    session_id: usize,
+    resource: String,
+    lang: String,
+    normalization: Normalization,
+}
+
+/// Most comparisons cached at once. The least recently used is dropped to
+/// make room for another.
+const MAX_CACHED_CHANGES: usize = 64;
 
-    let diff = TextDiff::configure().diff_words(dest.as_str(), source.as_str());
-    let changes: Vec<Change> = diff
+/// Whole comparisons, made again from scratch whenever the transcript
+/// changes.
+#[derive(Clone)]
+struct FullChanges {
+    translation_count: usize,
+    segment_count: usize,
+    changes: Vec<Change>,
+    used: Instant,
+}
+
+lazy_static! {
+    static ref CHANGES: Mutex<HashMap<ChangesKey, CachedChanges>> = Mutex::new(HashMap::new());
+    static ref FULL_CHANGES: Mutex<HashMap<ChangesKey, FullChanges>> = Mutex::new(HashMap::new());
+}
+
+/// Drops the cached comparisons of a session.
+pub fn forget_changes(session_id: usize) {
+    CHANGES
+        .lock()
+        .unwrap()
+        .retain(|key, _| key.session_id != session_id);
+    FULL_CHANGES
+        .lock()
+        .unwrap()
+        .retain(|key, _| key.session_id != session_id);
+}
+
+/// Makes room in a cache for another comparison, if it is full, by dropping
+/// the least recently used.
+fn make_room<T>(cache: &mut HashMap<ChangesKey, T>, used: impl Fn(&T) -> Instant) {
+    if cache.len() >= MAX_CACHED_CHANGES {
+        let oldest = cache
+            .iter()
+            .min_by_key(|(_, c)| used(c))
+            .map(|(k, _)| k.clone());
+        if let Some(oldest) = oldest {
+            cache.remove(&oldest);
+        }
+    }
+}
+
+fn diff(dest: &str, source: &str) -> Vec<Change> {
+    TextDiff::configure()
+        .diff_words(dest, source)
         .iter_all_changes()
         .map(|x| Change {
             change_type: match x.tag() {
@@ -68,7 +271,940 @@ pub async fn changes(resource_path: String, uuid: String, lang: String) -> E<Vec
             },
             content: x.value().to_string(),
         })
-        .collect();
-    log::trace!("Changes: {}", json!(changes).to_string());
+        .collect()
+}
+
+/// The length in bytes of the longest common prefix of two texts.
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map(|((i, _), _)| i)
+        .unwrap_or(a.len().min(b.len()))
+}
+
+/// The last point in `changes` before `offset` in the transcript where the
+/// two texts are in step: the number of changes up to and including the
+/// last equal change ending by then, and where it ends in the transcript
+/// and in the reference.
+fn split_point(changes: &[Change], offset: usize) -> (usize, usize, usize) {
+    let mut split = (0, 0, 0);
+    let (mut dest, mut source) = (0, 0);
+    for (i, change) in changes.iter().enumerate() {
+        let length = change.content.len();
+        match change.change_type.as_str() {
+            "equal" => {
+                dest += length;
+                source += length;
+            }
+            "delete" => dest += length,
+            _ => source += length,
+        }
+        if dest > offset {
+            break;
+        }
+        if change.change_type == "equal" {
+            split = (i + 1, dest, source);
+        }
+    }
+    split
+}
+
+/// Where sequence `sequence` starts in the normalized `transcript`.
+fn sequence_offset(
+    translations: &TranslationResponses,
+    sequence: usize,
+    transcript: &str,
+    lang: &str,
+    normalization: &Normalization,
+) -> usize {
+    let before = translations.text_until(sequence);
+    common_prefix(&normalization.apply(&before, lang), transcript)
+}
+
+/// Compares `transcript` with `reference`, keeping `changes`, a comparison
+/// of an earlier transcript with the same reference, up to `offset` in the
+/// transcript, and how many changes were kept.
+fn splice(
+    mut changes: Vec<Change>,
+    offset: usize,
+    transcript: &str,
+    reference: &str,
+) -> (Vec<Change>, usize) {
+    let (kept, dest, source) = split_point(&changes, offset);
+    changes.truncate(kept);
+    changes.extend(diff(&transcript[dest..], &reference[source..]));
+    (changes, kept)
+}
+
+/// The comparison of the session's transcript with the reference, from the
+/// cache if the transcript hasn't changed since. When it has, the
+/// comparison up to the start of the last sequence compared before, or to
+/// where the transcript changed if that is earlier, is kept and only the
+/// rest compared again.
+fn cached_changes(
+    session: &SessionData,
+    resource_path: &String,
+    lang: &String,
+    normalization: &Normalization,
+) -> E<CachedChanges> {
+    let key = ChangesKey {
+        session_id: session.id,
+        resource: resource_path.clone(),
+        lang: lang.clone(),
+        normalization: normalization.clone(),
+    };
+    let (translation_count, segment_count, sequence_count, transcript) = {
+        let translations = session.translations.lock().unwrap();
+        (
+            translations.translation_count()?,
+            translations.iter().count(),
+            translations.sequence_count(),
+            translations.to_string(),
+        )
+    };
+    let previous = {
+        let mut cache = CHANGES.lock().unwrap();
+        match cache.get_mut(&key) {
+            Some(c)
+                if c.translation_count == translation_count && c.segment_count == segment_count =>
+            {
+                c.used = Instant::now();
+                return Ok(c.clone());
+            }
+            _ => cache.remove(&key),
+        }
+    };
+    let transcript = normalization.apply(&transcript, lang);
+
+    log::debug!("Comparing");
+
+    let last_sequence = sequence_count.saturating_sub(1);
+    let cached = match previous {
+        Some(mut previous) => {
+            let offset = common_prefix(&previous.transcript, &transcript).min(sequence_offset(
+                &session.translations.lock().unwrap(),
+                previous.sequence_count.saturating_sub(1),
+                &transcript,
+                lang,
+                normalization,
+            ));
+            let (changes, kept) = splice(
+                std::mem::take(&mut previous.changes),
+                offset,
+                &transcript,
+                &previous.reference,
+            );
+            previous.changes = changes;
+            for changed in previous.changed_since.values_mut() {
+                *changed = (*changed).min(kept);
+            }
+            previous
+                .changed_since
+                .entry(last_sequence)
+                .or_insert(usize::MAX);
+            CachedChanges {
+                translation_count,
+                segment_count,
+                sequence_count,
+                transcript,
+                used: Instant::now(),
+                ..previous
+            }
+        }
+        None => {
+            let reference = normalization.apply(&get_translation(resource_path, lang)?, lang);
+            CachedChanges {
+                translation_count,
+                segment_count,
+                sequence_count,
+                changes: diff(&transcript, &reference),
+                reference,
+                transcript,
+                changed_since: BTreeMap::from([(last_sequence, usize::MAX)]),
+                used: Instant::now(),
+            }
+        }
+    };
+    log::trace!("Changes: {}", json!(cached.changes));
+
+    let mut cache = CHANGES.lock().unwrap();
+    make_room(&mut cache, |c| c.used);
+    cache.insert(key, cached.clone());
+    Ok(cached)
+}
+
+/// Compares the transcript with the reference, from the cache if the
+/// transcript hasn't changed since. Comparisons of the texts as they are,
+/// without normalization, are saved.
+fn full_changes(
+    session: &SessionData,
+    resource_path: &String,
+    lang: &String,
+    normalization: &Normalization,
+) -> E<Vec<Change>> {
+    let key = ChangesKey {
+        session_id: session.id,
+        resource: resource_path.clone(),
+        lang: lang.clone(),
+        normalization: normalization.clone(),
+    };
+    let (translation_count, segment_count, transcript) = {
+        let translations = session.translations.lock().unwrap();
+        (
+            translations.translation_count()?,
+            translations.iter().count(),
+            translations.to_string(),
+        )
+    };
+    if let Some(c) = FULL_CHANGES.lock().unwrap().get_mut(&key) {
+        if c.translation_count == translation_count && c.segment_count == segment_count {
+            c.used = Instant::now();
+            return Ok(c.changes.clone());
+        }
+    }
+
+    log::debug!("Comparing");
+
+    let reference = normalization.apply(&get_translation(resource_path, lang)?, lang);
+    let changes = diff(&normalization.apply(&transcript, lang), &reference);
+    log::trace!("Changes: {}", json!(changes));
+    if normalization.is_empty() {
+        crate::db::save_comparison(session.id, resource_path, lang, translation_count, &changes)?;
+    }
+
+    let mut cache = FULL_CHANGES.lock().unwrap();
+    make_room(&mut cache, |c| c.used);
+    cache.insert(
+        key,
+        FullChanges {
+            translation_count,
+            segment_count,
+            changes: changes.clone(),
+            used: Instant::now(),
+        },
+    );
     Ok(changes)
 }
+
+/// Compares the transcript with the reference.
+pub async fn changes(
+    resource_path: String,
+    uuid: String,
+    lang: String,
+    normalization: &Normalization,
+) -> E<Vec<Change>> {
+    let session = find_session(&uuid).await?;
+    full_changes(&session, &resource_path, &lang, normalization)
+}
+
+/// The end of a comparison, for clients which already have the start.
+#[derive(Clone, Debug, Serialize)]
+pub struct ChangesSince {
+    /// The index in the whole comparison of the first change given. Changes
+    /// from here on replace those the client has.
+    pub start: usize,
+    /// The last sequence compared, to ask for changes since next time.
+    pub sequence: usize,
+    pub translation_count: usize,
+    pub changes: Vec<Change>,
+}
+
+/// The changes from the start of sequence `since` on, and any earlier ones
+/// which are different from when `since` was the last sequence compared. If
+/// it never was, as far as the server remembers, the whole comparison is
+/// given.
+fn slice_since(
+    session: &SessionData,
+    cached: &CachedChanges,
+    lang: &str,
+    normalization: &Normalization,
+    since: Option<usize>,
+) -> ChangesSince {
+    let start = match since.and_then(|s| Some((s, cached.changed_since.get(&s)?))) {
+        Some((since, changed)) => {
+            let offset = sequence_offset(
+                &session.translations.lock().unwrap(),
+                since,
+                &cached.transcript,
+                lang,
+                normalization,
+            );
+            split_point(&cached.changes, offset).0.min(*changed)
+        }
+        None => 0,
+    };
+    ChangesSince {
+        start,
+        sequence: cached.sequence_count.saturating_sub(1),
+        translation_count: cached.translation_count,
+        changes: cached.changes[start..].to_vec(),
+    }
+}
+
+pub async fn changes_since(
+    resource_path: String,
+    uuid: String,
+    lang: String,
+    normalization: &Normalization,
+    since: usize,
+) -> E<ChangesSince> {
+    let session = find_session(&uuid).await?;
+    let cached = cached_changes(&session, &resource_path, &lang, normalization)?;
+    Ok(slice_since(
+        &session,
+        &cached,
+        &lang,
+        normalization,
+        Some(since),
+    ))
+}
+
+/// The comparison of a session being recorded, as it is sent to the
+/// interpreter after each segment.
+#[derive(Clone, Debug, Serialize)]
+pub struct LiveComparison {
+    #[serde(flatten)]
+    pub changes: ChangesSince,
+    /// Word counts of the comparison up to the point in the reference the
+    /// interpreter has reached, the end of the last words they have in
+    /// common.
+    pub counts: ChangeCounts,
+    /// The proportion of the reference up to that point which is in the
+    /// transcript.
+    pub coverage: f64,
+    /// How far through the reference that point is, by words, from 0 to 1.
+    pub progress: f64,
+    /// The transcript measured against the reference up to that point.
+    pub metrics: Metrics,
+}
+
+/// Compares a session being recorded with the reference of its resource in
+/// the session's language, giving the changes since sequence `since`, or
+/// all of them.
+pub fn live(
+    session: &SessionData,
+    resource_path: &String,
+    since: Option<usize>,
+) -> E<LiveComparison> {
+    let normalization = Normalization::default();
+    let cached = cached_changes(session, resource_path, &session.language, &normalization)?;
+    let reached = cached
+        .changes
+        .iter()
+        .rposition(|c| c.change_type == "equal" && !c.content.trim().is_empty())
+        .map_or(0, |i| i + 1);
+    let (_, _, reference_end) = split_point(&cached.changes[..reached], usize::MAX);
+    let reference = &cached.reference[..reference_end];
+    let counts = ChangeCounts::from_changes(&cached.changes[..reached]);
+    let total_words = crate::metrics::words(&cached.reference).len();
+    Ok(LiveComparison {
+        coverage: counts.coverage(),
+        progress: if total_words == 0 {
+            0.0
+        } else {
+            crate::metrics::words(reference).len() as f64 / total_words as f64
+        },
+        metrics: crate::metrics::compute(&cached.transcript, reference),
+        counts,
+        changes: slice_since(session, &cached, &session.language, &normalization, since),
+    })
+}
+
+pub async fn metrics(resource_path: String, uuid: String, lang: String) -> E<Metrics> {
+    let (session, source) = session_and_reference(&resource_path, &uuid, &lang).await?;
+    Ok(crate::metrics::compute(&session.transcript()?, &source))
+}
+
+/// How sentences of the reference correspond to those of the transcript.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlignmentKind {
+    /// One or two reference sentences interpreted as as many sentences.
+    Match,
+    /// A reference sentence missing from the transcript.
+    Omission,
+    /// A transcript sentence with nothing corresponding in the reference.
+    Addition,
+    /// Two reference sentences interpreted as one.
+    Merge,
+    /// One reference sentence interpreted as two.
+    Split,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AlignedPair {
+    pub kind: AlignmentKind,
+    pub reference: String,
+    pub transcript: String,
+    /// How many words the two sides share, from 0 to 1.
+    pub similarity: f64,
+}
+
+/// Splits a text into sentences at full stops, question and exclamation
+/// marks followed by whitespace, and at line breaks.
+pub fn split_sentences(text: &str) -> Vec<String> {
+    let mut sentences = vec![];
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let end = match c {
+            '\n' | '\r' => true,
+            '.' | '!' | '?' | '…' | '。' => chars.peek().is_none_or(|n| n.is_whitespace()),
+            _ => false,
+        };
+        if c != '\n' && c != '\r' {
+            current.push(c);
+        }
+        if end {
+            let sentence = current.trim();
+            if !sentence.is_empty() {
+                sentences.push(sentence.to_string());
+            }
+            current.clear();
+        }
+    }
+    let sentence = current.trim();
+    if !sentence.is_empty() {
+        sentences.push(sentence.to_string());
+    }
+    sentences
+}
+
+/// Variance of the difference in length between corresponding sentences,
+/// from Gale and Church.
+const LENGTH_VARIANCE: f64 = 6.8;
+/// How much a lack of shared words adds to the cost of aligning a sentence.
+const LEXICAL_WEIGHT: f64 = 6.0;
+
+/// Steps through both texts: how many reference and transcript sentences
+/// each covers, with its prior probability.
+const BEADS: [(usize, usize, f64, AlignmentKind); 6] = [
+    (1, 1, 0.89, AlignmentKind::Match),
+    (1, 0, 0.01, AlignmentKind::Omission),
+    (0, 1, 0.01, AlignmentKind::Addition),
+    (2, 1, 0.045, AlignmentKind::Merge),
+    (1, 2, 0.045, AlignmentKind::Split),
+    (2, 2, 0.011, AlignmentKind::Match),
+];
+
+/// The standard normal cumulative distribution function, approximated as
+/// in Abramowitz and Stegun 26.2.17.
+fn normal_cdf(z: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.2316419 * z.abs());
+    let density = (-z * z / 2.0).exp() / (2.0 * std::f64::consts::PI).sqrt();
+    let tail = density
+        * t
+        * (0.319381530
+            + t * (-0.356563782 + t * (1.781477937 + t * (-1.821255978 + t * 1.330274429))));
+    if z >= 0.0 {
+        1.0 - tail
+    } else {
+        tail
+    }
+}
+
+/// Gale and Church's cost of aligning text of these lengths in
+/// characters, given the expected ratio of transcript to reference length.
+fn length_cost(reference_length: usize, transcript_length: usize, ratio: f64, prior: f64) -> f64 {
+    let r = reference_length as f64;
+    let t = transcript_length as f64;
+    let mean = (r + t / ratio) / 2.0;
+    let probability = if mean == 0.0 {
+        1.0
+    } else {
+        let delta = (t - r * ratio) / (mean * LENGTH_VARIANCE).sqrt();
+        (2.0 * (1.0 - normal_cdf(delta.abs()))).max(f64::MIN_POSITIVE)
+    };
+    -prior.ln() - probability.ln()
+}
+
+/// Aligns the sentences of the reference and the transcript, Gale and
+/// Church style, with the cost of each pairing lowered by the words the two
+/// sides share.
+pub fn align_sentences(reference: &str, transcript: &str) -> Vec<AlignedPair> {
+    let reference = split_sentences(reference);
+    let transcript = split_sentences(transcript);
+    align(&reference, &transcript)
+        .into_iter()
+        .map(|(r, t, kind)| {
+            let r = reference[r].join(" ");
+            let t = transcript[t].join(" ");
+            AlignedPair {
+                kind,
+                similarity: crate::metrics::similarity(&r, &t),
+                reference: r,
+                transcript: t,
+            }
+        })
+        .collect()
+}
+
+/// The beads of the alignment of two lists of sentences, in order: the
+/// sentences each takes from the reference and the transcript.
+fn align(
+    reference: &[String],
+    transcript: &[String],
+) -> Vec<(Range<usize>, Range<usize>, AlignmentKind)> {
+    let length = |s: &[String]| s.iter().map(|x| x.chars().count()).sum::<usize>();
+    let ratio = match (length(reference), length(transcript)) {
+        (0, _) | (_, 0) => 1.0,
+        (r, t) => t as f64 / r as f64,
+    };
+
+    let (n, m) = (reference.len(), transcript.len());
+    let mut cost = vec![vec![f64::INFINITY; m + 1]; n + 1];
+    let mut step = vec![vec![0; m + 1]; n + 1];
+    cost[0][0] = 0.0;
+    for i in 0..=n {
+        for j in 0..=m {
+            for (b, (di, dj, prior, _)) in BEADS.iter().enumerate() {
+                if *di > i || *dj > j || cost[i - di][j - dj].is_infinite() {
+                    continue;
+                }
+                let r = reference[i - di..i].join(" ");
+                let t = transcript[j - dj..j].join(" ");
+                let mut c = cost[i - di][j - dj]
+                    + length_cost(r.chars().count(), t.chars().count(), ratio, *prior);
+                if *di > 0 && *dj > 0 {
+                    let sentences = (di + dj) as f64 / 2.0;
+                    c += LEXICAL_WEIGHT * sentences * (1.0 - crate::metrics::similarity(&r, &t));
+                }
+                if c < cost[i][j] {
+                    cost[i][j] = c;
+                    step[i][j] = b;
+                }
+            }
+        }
+    }
+
+    let mut beads = vec![];
+    let (mut i, mut j) = (n, m);
+    while i > 0 || j > 0 {
+        let (di, dj, _, kind) = BEADS[step[i][j]];
+        beads.push((i - di..i, j - dj..j, kind));
+        i -= di;
+        j -= dj;
+    }
+    beads.reverse();
+    beads
+}
+
+pub async fn alignment(resource_path: String, uuid: String, lang: String) -> E<Vec<AlignedPair>> {
+    let (session, source) = session_and_reference(&resource_path, &uuid, &lang).await?;
+    Ok(align_sentences(&source, &session.transcript()?))
+}
+
+/// A transcript sentence and the closest sentence of any reference.
+#[derive(Clone, Debug, Serialize)]
+pub struct ClosestReference {
+    pub transcript: String,
+    /// Empty if no reference has anything corresponding to the sentence.
+    pub reference: String,
+    /// Which of the references, in the order of the resource's metadata.
+    pub reference_index: Option<usize>,
+    pub similarity: f64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct MultiReferenceComparison {
+    pub references: usize,
+    pub sentences: Vec<ClosestReference>,
+    /// The mean similarity of the transcript sentences to their closest
+    /// references.
+    pub similarity: f64,
+    /// The words of the transcript, `equal` if they are in any reference and
+    /// `delete` if they are in none, ignoring case and punctuation.
+    pub changes: Vec<Change>,
+    /// The proportion of transcript words which are in some reference.
+    pub coverage: f64,
+}
+
+/// Compares the transcript with every reference translation into the
+/// language: each of its sentences is scored against whichever reference
+/// renders it most similarly, and each of its words counts as covered if
+/// any reference has it.
+pub fn compare_references(references: &[String], transcript: &str) -> MultiReferenceComparison {
+    let sentences = split_sentences(transcript);
+    let mut closest: Vec<ClosestReference> = sentences
+        .iter()
+        .map(|s| ClosestReference {
+            transcript: s.clone(),
+            reference: String::new(),
+            reference_index: None,
+            similarity: 0.0,
+        })
+        .collect();
+    let mut vocabulary = HashSet::new();
+    for (index, reference) in references.iter().enumerate() {
+        let reference_sentences = split_sentences(reference);
+        for (r, t, _) in align(&reference_sentences, &sentences) {
+            if r.is_empty() || t.is_empty() {
+                continue;
+            }
+            let reference = reference_sentences[r].join(" ");
+            let similarity =
+                crate::metrics::similarity(&reference, &sentences[t.clone()].join(" "));
+            for sentence in &mut closest[t] {
+                if sentence.reference_index.is_none() || similarity > sentence.similarity {
+                    sentence.reference = reference.clone();
+                    sentence.reference_index = Some(index);
+                    sentence.similarity = similarity;
+                }
+            }
+        }
+
+        vocabulary.extend(crate::metrics::words(reference));
+    }
+
+    // Punctuation and whitespace count as covered.
+    let covered: Vec<(String, Option<bool>)> = transcript
+        .split_inclusive(char::is_whitespace)
+        .map(|token| {
+            let word = crate::metrics::words(token).join("");
+            let covered = (!word.is_empty()).then(|| vocabulary.contains(&word));
+            (token.to_string(), covered)
+        })
+        .collect();
+    let words = covered.iter().filter_map(|(_, c)| *c).collect::<Vec<_>>();
+    let coverage = if words.is_empty() {
+        0.0
+    } else {
+        words.iter().filter(|c| **c).count() as f64 / words.len() as f64
+    };
+    let similarity = if closest.is_empty() {
+        0.0
+    } else {
+        closest.iter().map(|c| c.similarity).sum::<f64>() / closest.len() as f64
+    };
+    MultiReferenceComparison {
+        references: references.len(),
+        sentences: closest,
+        similarity,
+        changes: covered
+            .into_iter()
+            .map(|(content, covered)| Change {
+                change_type: if covered == Some(false) {
+                    "delete"
+                } else {
+                    "equal"
+                }
+                .to_string(),
+                content,
+            })
+            .collect(),
+        coverage,
+    }
+}
+
+pub async fn references(
+    resource_path: String,
+    uuid: String,
+    lang: String,
+) -> E<MultiReferenceComparison> {
+    let references = get_references(&resource_path, &lang)?;
+    let session = find_session(&uuid).await?;
+    Ok(compare_references(&references, &session.transcript()?))
+}
+
+/// Checks the numbers and names of the reference against the transcript.
+pub async fn entities(resource_path: String, uuid: String, lang: String) -> E<EntityAccuracy> {
+    let (session, source) = session_and_reference(&resource_path, &uuid, &lang).await?;
+    Ok(entities::check(&source, &session.transcript()?, &lang))
+}
+
+/// Checks which glossary terms of the speech the transcript renders.
+pub async fn terminology(resource_path: String, uuid: String, lang: String) -> E<Terminology> {
+    let metadata = load_metadata(&resource_path)?;
+    let terms = glossary::load(&metadata, &resource_path)?;
+    let source = match &metadata.transcript {
+        Some(t) => Some(fs::read_to_string(format!(
+            "{}/{}",
+            metadata.enclosing_directory, t
+        ))?),
+        None => None,
+    };
+    let (session, _) = session_and_reference(&resource_path, &uuid, &lang).await?;
+    Ok(glossary::check(
+        &terms,
+        source.as_deref(),
+        &metadata.native,
+        &session.transcript()?,
+        &lang,
+    ))
+}
+
+/// Compares the transcript with the timed reference cue by cue.
+pub async fn timed(
+    resource_path: String,
+    uuid: String,
+    lang: String,
+    max_lag: f64,
+) -> E<TimedComparison> {
+    let metadata = load_metadata(&resource_path)?;
+    let cues = timed::load(&metadata, &resource_path, &lang)?;
+    let (session, _) = session_and_reference(&resource_path, &uuid, &lang).await?;
+    let segments = timed::session_segments(&session, metadata.skip as f64)?;
+    Ok(timed::compare(&cues, &segments, max_lag))
+}
+
+/// The ear-voice span over the course of the session, measured against the
+/// timed reference.
+pub async fn ear_voice_span(
+    resource_path: String,
+    uuid: String,
+    lang: String,
+    max_lag: f64,
+) -> E<EarVoiceSpan> {
+    let comparison = timed(resource_path, uuid, lang, max_lag).await?;
+    Ok(timed::ear_voice_span(&comparison))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate::TranslationResponse;
+
+    /// The transcript and reference a comparison was made of.
+    fn texts(changes: &[Change]) -> (String, String) {
+        let (mut transcript, mut reference) = (String::new(), String::new());
+        for change in changes {
+            match change.change_type.as_str() {
+                "equal" => {
+                    transcript.push_str(&change.content);
+                    reference.push_str(&change.content);
+                }
+                "delete" => transcript.push_str(&change.content),
+                _ => reference.push_str(&change.content),
+            }
+        }
+        (transcript, reference)
+    }
+
+    fn translations(sequences: &[&str]) -> TranslationResponses {
+        let mut translations = TranslationResponses::new();
+        for (i, text) in sequences.iter().enumerate() {
+            translations
+                .add_translation(&TranslationResponse {
+                    sequence_number: i,
+                    translation: text.to_string(),
+                    num_segments: 1,
+                    segment_number: 0,
+                    segment_start: 0,
+                    segment_end: 1,
+                    uuid: String::new(),
+                })
+                .unwrap();
+        }
+        translations
+    }
+
+    #[test]
+    fn common_prefixes() {
+        assert_eq!(common_prefix("abc", "abd"), 2);
+        assert_eq!(common_prefix("abc", "ab"), 2);
+        assert_eq!(common_prefix("ab", "abc"), 2);
+        assert_eq!(common_prefix("abc", "abc"), 3);
+        assert_eq!(common_prefix("", "abc"), 0);
+        assert_eq!(common_prefix("xbc", "abc"), 0);
+        // in bytes, and never inside a character.
+        assert_eq!(common_prefix("für", "füx"), 3);
+        assert_eq!(common_prefix("fär", "für"), 1);
+    }
+
+    #[test]
+    fn split_points() {
+        let transcript = "we went home late";
+        let reference = "we walked home late";
+        let changes = diff(transcript, reference);
+
+        assert_eq!(split_point(&changes, 0), (0, 0, 0));
+        // "we " is in both; "went" and "walked" aren't.
+        for offset in 3..8 {
+            let (kept, dest, source) = split_point(&changes, offset);
+            assert_eq!((&transcript[..dest], &reference[..source]), ("we ", "we "));
+            assert_eq!(texts(&changes[..kept]), ("we ".into(), "we ".into()));
+        }
+        let (kept, dest, source) = split_point(&changes, transcript.len());
+        assert_eq!(kept, changes.len());
+        assert_eq!((dest, source), (transcript.len(), reference.len()));
+    }
+
+    #[test]
+    fn split_points_of_nothing_in_common() {
+        let changes = diff("yes", "no");
+        assert_eq!(split_point(&changes, 3), (0, 0, 0));
+        assert_eq!(split_point(&[], 10), (0, 0, 0));
+    }
+
+    #[test]
+    fn sequence_offsets() {
+        let translations = translations(&["Hello world. ", "How are you? ", "Fine."]);
+        let transcript = translations.to_string();
+        let none = Normalization::default();
+        assert_eq!(
+            sequence_offset(&translations, 0, &transcript, "en", &none),
+            0
+        );
+        assert_eq!(
+            sequence_offset(&translations, 1, &transcript, "en", &none),
+            13
+        );
+        assert_eq!(
+            sequence_offset(&translations, 2, &transcript, "en", &none),
+            26
+        );
+        assert_eq!(
+            sequence_offset(&translations, 3, &transcript, "en", &none),
+            transcript.len()
+        );
+
+        let lowercase = Normalization {
+            lowercase: true,
+            punctuation: true,
+            ..Default::default()
+        };
+        let normalized = lowercase.apply(&transcript, "en");
+        assert_eq!(
+            sequence_offset(&translations, 1, &normalized, "en", &lowercase),
+            "hello world ".len()
+        );
+    }
+
+    #[test]
+    fn sequence_offsets_of_a_changed_transcript() {
+        // the start of the transcript isn't what it was when the sequences
+        // before were put together, so they only go as far as it agrees.
+        let translations = translations(&["We went home. ", "It was late."]);
+        let transcript = "We want home. It was late.";
+        let none = Normalization::default();
+        assert_eq!(
+            sequence_offset(&translations, 1, transcript, "en", &none),
+            4
+        );
+    }
+
+    #[test]
+    fn splicing_onto_a_longer_transcript() {
+        let reference = "the cat sat on the mat and slept";
+        let before = "the cat sat on";
+        let after = "the cat sat on the mat and slept";
+        let (changes, kept) = splice(diff(before, reference), before.len(), after, reference);
+        assert_eq!(texts(&changes), (after.into(), reference.into()));
+        assert_eq!(changes, diff(after, reference));
+        assert!(kept > 0);
+    }
+
+    #[test]
+    fn splicing_after_words_before_the_split_change() {
+        let reference = "we walked home. it was late.";
+        let before = "we walked home. it was";
+        // the last sequence was transcribed again, and now the end of the
+        // one before it reads differently too.
+        let after = "we walked homes. it was late.";
+        let previous = diff(before, reference);
+        let sequence_start = "we walked home. ".len();
+        let offset = common_prefix(before, after).min(sequence_start);
+        assert_eq!(offset, "we walked home".len());
+
+        let (changes, kept) = splice(previous.clone(), offset, after, reference);
+        assert_eq!(texts(&changes), (after.into(), reference.into()));
+        assert_eq!(changes[..kept], previous[..kept]);
+        assert_eq!(texts(&changes[..kept]).0, "we walked ");
+        assert!(changes
+            .iter()
+            .any(|c| c.change_type == "delete" && c.content == "homes."));
+    }
+
+    #[test]
+    fn splicing_a_shorter_transcript() {
+        let reference = "one two three four";
+        let before = "one two three four five";
+        let after = "one two";
+        let offset = common_prefix(before, after);
+        let (changes, _) = splice(diff(before, reference), offset, after, reference);
+        assert_eq!(texts(&changes), (after.into(), reference.into()));
+    }
+
+    fn kinds(pairs: &[AlignedPair]) -> Vec<AlignmentKind> {
+        pairs.iter().map(|p| p.kind).collect()
+    }
+
+    #[test]
+    fn sentences() {
+        assert_eq!(
+            split_sentences("Guten Tag. Wie geht es? Gut!"),
+            vec!["Guten Tag.", "Wie geht es?", "Gut!"]
+        );
+        // only stops followed by whitespace end sentences, so abbreviations
+        // inside one do too.
+        assert_eq!(
+            split_sentences("Es stieg um 3.5 Prozent, d.h. stark."),
+            vec!["Es stieg um 3.5 Prozent, d.h.", "stark."]
+        );
+        assert_eq!(
+            split_sentences("Erstens\r\nzweitens\n\ndrittens… und"),
+            vec!["Erstens", "zweitens", "drittens…", "und"]
+        );
+        assert!(split_sentences("").is_empty());
+        assert!(split_sentences(" \n\n  ").is_empty());
+    }
+
+    #[test]
+    fn aligning_the_same_sentences() {
+        let text =
+            "The meeting opened at nine. The chair welcomed the guests. Then the vote began.";
+        let pairs = align_sentences(text, text);
+        assert_eq!(kinds(&pairs), vec![AlignmentKind::Match; 3]);
+        assert!(pairs.iter().all(|p| p.similarity == 1.0));
+        assert_eq!(pairs[1].reference, "The chair welcomed the guests.");
+    }
+
+    #[test]
+    fn aligning_two_sentences_with_one() {
+        let reference = "The meeting opened at nine. The chair welcomed all the guests. \
+                         Then the vote on the budget began.";
+        let transcript = "The meeting opened at nine and the chair welcomed all the guests. \
+                          Then the vote on the budget began.";
+        let pairs = align_sentences(reference, transcript);
+        assert_eq!(
+            kinds(&pairs),
+            vec![AlignmentKind::Merge, AlignmentKind::Match]
+        );
+        assert_eq!(
+            pairs[0].reference,
+            "The meeting opened at nine. The chair welcomed all the guests."
+        );
+        assert!(pairs[0].similarity > 0.8);
+    }
+
+    #[test]
+    fn aligning_one_sentence_with_two() {
+        let reference = "The meeting opened at nine and the chair welcomed all the guests. \
+                         Then the vote on the budget began.";
+        let transcript = "The meeting opened at nine. The chair welcomed all the guests. \
+                          Then the vote on the budget began.";
+        let pairs = align_sentences(reference, transcript);
+        assert_eq!(
+            kinds(&pairs),
+            vec![AlignmentKind::Split, AlignmentKind::Match]
+        );
+        assert_eq!(
+            pairs[0].transcript,
+            "The meeting opened at nine. The chair welcomed all the guests."
+        );
+    }
+
+    #[test]
+    fn aligning_empty_texts() {
+        assert!(align_sentences("", "").is_empty());
+        assert_eq!(
+            kinds(&align_sentences("One. Two.", "")),
+            vec![AlignmentKind::Omission; 2]
+        );
+        assert_eq!(
+            kinds(&align_sentences("", "One. Two.")),
+            vec![AlignmentKind::Addition; 2]
+        );
+    }
+}
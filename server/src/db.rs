@@ -0,0 +1,988 @@
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row, ToSql};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::annotation::{Annotation, AnnotationRequest, Side};
+use crate::assignment::{Assignment, NewAssignment};
+use crate::auth::{login_scopes, ApiToken, Scope, User};
+use crate::compare::Change;
+use crate::error::{Er, E};
+use crate::session::{SessionData, SessionState};
+use crate::translate::{TranslationResponse, TranslationResponses};
+
+/// The tables of a new database. Later changes to them go in `MIGRATIONS`.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS users (
+    id INTEGER PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS api_tokens (
+    id INTEGER PRIMARY KEY,
+    token_hash TEXT NOT NULL UNIQUE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    scopes TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS logins (
+    token TEXT PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    expires_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS assignments (
+    id INTEGER PRIMARY KEY,
+    teacher_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    resource TEXT NOT NULL,
+    lang TEXT NOT NULL,
+    deadline TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    uuid TEXT NOT NULL UNIQUE,
+    language TEXT NOT NULL,
+    resource TEXT,
+    sample_rate INTEGER NOT NULL,
+    sequence_number INTEGER NOT NULL DEFAULT 0,
+    recording_file TEXT,
+    transcript_file TEXT,
+    segments_file TEXT,
+    state TEXT NOT NULL DEFAULT 'live',
+    user_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    assignment_id INTEGER REFERENCES assignments(id) ON DELETE SET NULL,
+    starred INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS segments (
+    session_id INTEGER NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    sequence_number INTEGER NOT NULL,
+    segment_number INTEGER NOT NULL,
+    num_segments INTEGER NOT NULL,
+    segment_start INTEGER NOT NULL,
+    segment_end INTEGER NOT NULL,
+    translation TEXT NOT NULL,
+    PRIMARY KEY (session_id, sequence_number, segment_number)
+);
+CREATE TABLE IF NOT EXISTS sequences (
+    session_id INTEGER NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    sequence_number INTEGER NOT NULL,
+    start_ms INTEGER NOT NULL,
+    PRIMARY KEY (session_id, sequence_number)
+);
+CREATE TABLE IF NOT EXISTS comparisons (
+    session_id INTEGER NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    resource TEXT NOT NULL,
+    lang TEXT NOT NULL,
+    translation_count INTEGER NOT NULL,
+    changes TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (session_id, resource, lang)
+);
+CREATE TABLE IF NOT EXISTS annotations (
+    id INTEGER PRIMARY KEY,
+    session_id INTEGER NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    author_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    start_time REAL,
+    end_time REAL,
+    side TEXT,
+    quote TEXT,
+    comment TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+";
+
+const INDEXES: &str = "
+CREATE INDEX IF NOT EXISTS sessions_created_at ON sessions(created_at);
+CREATE INDEX IF NOT EXISTS sessions_updated_at ON sessions(updated_at);
+CREATE INDEX IF NOT EXISTS sessions_resource ON sessions(resource);
+CREATE INDEX IF NOT EXISTS sessions_user_id ON sessions(user_id);
+CREATE INDEX IF NOT EXISTS sessions_assignment_id ON sessions(assignment_id);
+CREATE INDEX IF NOT EXISTS annotations_session_id ON annotations(session_id);
+";
+
+type Migration = fn(&Connection) -> E<()>;
+
+/// Changes to the database, in order. `PRAGMA user_version` is how many
+/// have been made, so that each is made once. New ones go at the end.
+const MIGRATIONS: &[Migration] = &[create_tables];
+
+const SESSION_COLUMNS: &str = "id, uuid, language, resource, sample_rate, sequence_number, \
+                               recording_file, transcript_file, segments_file, created_at, \
+                               updated_at, starred, state, user_id, \
+                               assignment_id";
+
+lazy_static! {
+    static ref DB: Mutex<Connection> = {
+        let filename = std::env::var("DATABASE_FILE")
+            .ok()
+            .filter(|file| !file.is_empty())
+            .unwrap_or("../terplounge.sqlite".to_string());
+        log::debug!("Opening database {}", filename);
+        let connection = Connection::open(filename).expect("Couldn't open database");
+        connection
+            .execute_batch("PRAGMA foreign_keys = ON; PRAGMA journal_mode = WAL;")
+            .expect("Couldn't configure database");
+        Mutex::new(connection)
+    };
+}
+
+/// Brings the database up to date. Called once at startup.
+pub fn init() -> E<()> {
+    migrate(&mut DB.lock().unwrap())
+}
+
+fn migrate(connection: &mut Connection) -> E<()> {
+    let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(Er::new(format!(
+            "Database is at version {}, but this server only knows up to {}",
+            version,
+            MIGRATIONS.len()
+        )));
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        log::info!("Migrating database to version {}", i + 1);
+        let transaction = connection.transaction()?;
+        migration(&transaction)?;
+        transaction.pragma_update(None, "user_version", i + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+fn create_tables(connection: &Connection) -> E<()> {
+    connection.execute_batch(SCHEMA)?;
+    connection.execute_batch(INDEXES)?;
+    Ok(())
+}
+
+pub fn insert_session(session: &SessionData) -> E<()> {
+    DB.lock().unwrap().execute(
+        "INSERT INTO sessions (id, uuid, language, resource, sample_rate, sequence_number, \
+         recording_file, transcript_file, segments_file, created_at, updated_at, starred, \
+         state, user_id, assignment_id) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+        params![
+            session.id,
+            session.uuid.to_string(),
+            session.language,
+            session.resource,
+            session.sample_rate,
+            session.sequence_number,
+            session.recording_file,
+            session.transcript_file,
+            session.segments_file,
+            session.created_at,
+            session.updated_at,
+            session.starred,
+            session.state,
+            session.user_id,
+            session.assignment_id,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Called when a session has been completely transcribed.
+pub fn finalize_session(session: &SessionData) -> E<()> {
+    DB.lock().unwrap().execute(
+        "UPDATE sessions SET sequence_number = ?1, state = ?2, updated_at = ?3 WHERE id = ?4",
+        params![
+            session.sequence_number,
+            SessionState::Finished,
+            session.updated_at,
+            session.id
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn set_state(id: &usize, state: SessionState) -> E<()> {
+    DB.lock().unwrap().execute(
+        "UPDATE sessions SET state = ?1, updated_at = ?2 WHERE id = ?3",
+        params![state, Utc::now(), id],
+    )?;
+    Ok(())
+}
+
+pub fn set_starred(id: &usize, starred: bool) -> E<()> {
+    DB.lock().unwrap().execute(
+        "UPDATE sessions SET starred = ?1 WHERE id = ?2",
+        params![starred, id],
+    )?;
+    Ok(())
+}
+
+/// Removes a session, its segments, sequences, comparisons and annotations.
+pub fn delete_session(id: &usize) -> E<()> {
+    DB.lock()
+        .unwrap()
+        .execute("DELETE FROM sessions WHERE id = ?1", params![id])?;
+    Ok(())
+}
+
+pub fn insert_user(username: &str, password_hash: &str) -> E<usize> {
+    let connection = DB.lock().unwrap();
+    connection.execute(
+        "INSERT INTO users (username, password_hash, created_at) VALUES (?1, ?2, ?3)",
+        params![username, password_hash, Utc::now()],
+    )?;
+    Ok(connection.last_insert_rowid() as usize)
+}
+
+/// The user with this name, and their password hash.
+pub fn find_user(username: &str) -> E<Option<(User, String)>> {
+    let user = DB
+        .lock()
+        .unwrap()
+        .query_row(
+            "SELECT id, username, password_hash FROM users WHERE username = ?1",
+            params![username],
+            |row| {
+                Ok((
+                    User {
+                        id: row.get(0)?,
+                        scopes: login_scopes(&row.get::<_, String>(1)?),
+                        username: row.get(1)?,
+                    },
+                    row.get(2)?,
+                ))
+            },
+        )
+        .optional()?;
+    Ok(user)
+}
+
+pub fn insert_login(token: &str, user_id: &usize, expires_at: DateTime<Utc>) -> E<()> {
+    DB.lock().unwrap().execute(
+        "INSERT INTO logins (token, user_id, expires_at) VALUES (?1, ?2, ?3)",
+        params![token, user_id, expires_at],
+    )?;
+    Ok(())
+}
+
+/// The user logged in with this token, if it hasn't expired.
+pub fn find_login(token: &str) -> E<Option<User>> {
+    let user = DB
+        .lock()
+        .unwrap()
+        .query_row(
+            "SELECT users.id, users.username FROM logins JOIN users ON users.id = logins.user_id \
+             WHERE logins.token = ?1 AND logins.expires_at > ?2",
+            params![token, Utc::now()],
+            |row| {
+                Ok(User {
+                    id: row.get(0)?,
+                    scopes: login_scopes(&row.get::<_, String>(1)?),
+                    username: row.get(1)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(user)
+}
+
+pub fn delete_login(token: &str) -> E<()> {
+    DB.lock()
+        .unwrap()
+        .execute("DELETE FROM logins WHERE token = ?1", params![token])?;
+    Ok(())
+}
+
+pub fn insert_api_token(token_hash: &str, user_id: &usize, name: &str, scopes: &[Scope]) -> E<()> {
+    DB.lock().unwrap().execute(
+        "INSERT INTO api_tokens (token_hash, user_id, name, scopes, created_at) \
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![token_hash, user_id, name, scopes_to_sql(scopes), Utc::now()],
+    )?;
+    Ok(())
+}
+
+/// The owner of the token with this hash, with the token's scopes.
+pub fn find_api_token(token_hash: &str) -> E<Option<User>> {
+    let user = DB
+        .lock()
+        .unwrap()
+        .query_row(
+            "SELECT users.id, users.username, api_tokens.scopes FROM api_tokens \
+             JOIN users ON users.id = api_tokens.user_id WHERE api_tokens.token_hash = ?1",
+            params![token_hash],
+            |row| {
+                Ok(User {
+                    id: row.get(0)?,
+                    username: row.get(1)?,
+                    scopes: scopes_from_sql(&row.get::<_, String>(2)?),
+                })
+            },
+        )
+        .optional()?;
+    Ok(user)
+}
+
+pub fn list_api_tokens(user_id: &usize) -> E<Vec<ApiToken>> {
+    let connection = DB.lock().unwrap();
+    let mut statement = connection.prepare(
+        "SELECT id, name, scopes, created_at FROM api_tokens WHERE user_id = ?1 ORDER BY id",
+    )?;
+    let tokens = statement
+        .query_map(params![user_id], |row| {
+            Ok(ApiToken {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                scopes: scopes_from_sql(&row.get::<_, String>(2)?),
+                created_at: row.get(3)?,
+            })
+        })?
+        .collect::<Result<Vec<ApiToken>, rusqlite::Error>>()?;
+    Ok(tokens)
+}
+
+/// Returns whether a token was deleted.
+pub fn delete_api_token(id: &usize, user_id: &usize) -> E<bool> {
+    let count = DB.lock().unwrap().execute(
+        "DELETE FROM api_tokens WHERE id = ?1 AND user_id = ?2",
+        params![id, user_id],
+    )?;
+    Ok(count > 0)
+}
+
+fn scopes_to_sql(scopes: &[Scope]) -> String {
+    scopes
+        .iter()
+        .map(|s| s.as_str())
+        .collect::<Vec<&str>>()
+        .join(",")
+}
+
+fn scopes_from_sql(scopes: &str) -> Vec<Scope> {
+    scopes.split(',').filter_map(Scope::parse).collect()
+}
+
+pub fn insert_assignment(teacher_id: &usize, assignment: &NewAssignment) -> E<usize> {
+    let connection = DB.lock().unwrap();
+    connection.execute(
+        "INSERT INTO assignments (teacher_id, name, resource, lang, deadline, created_at) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            teacher_id,
+            assignment.name,
+            assignment.resource,
+            assignment.lang,
+            assignment.deadline,
+            Utc::now()
+        ],
+    )?;
+    Ok(connection.last_insert_rowid() as usize)
+}
+
+const ASSIGNMENT_COLUMNS: &str = "id, teacher_id, name, resource, lang, deadline, created_at";
+
+fn assignment_from_row(row: &Row) -> rusqlite::Result<Assignment> {
+    Ok(Assignment {
+        id: row.get(0)?,
+        teacher_id: row.get(1)?,
+        name: row.get(2)?,
+        resource: row.get(3)?,
+        lang: row.get(4)?,
+        deadline: row.get(5)?,
+        created_at: row.get(6)?,
+    })
+}
+
+pub fn load_assignment(id: &usize) -> E<Option<Assignment>> {
+    let assignment = DB
+        .lock()
+        .unwrap()
+        .query_row(
+            &format!(
+                "SELECT {} FROM assignments WHERE id = ?1",
+                ASSIGNMENT_COLUMNS
+            ),
+            params![id],
+            assignment_from_row,
+        )
+        .optional()?;
+    Ok(assignment)
+}
+
+/// The assignments set by the given teacher, or all of them, newest first.
+pub fn load_assignments(teacher_id: Option<usize>) -> E<Vec<Assignment>> {
+    let connection = DB.lock().unwrap();
+    let mut statement = connection.prepare(&format!(
+        "SELECT {} FROM assignments WHERE ?1 IS NULL OR teacher_id = ?1 ORDER BY deadline DESC",
+        ASSIGNMENT_COLUMNS
+    ))?;
+    let assignments = statement
+        .query_map(params![teacher_id], assignment_from_row)?
+        .collect::<Result<Vec<Assignment>, rusqlite::Error>>()?;
+    Ok(assignments)
+}
+
+/// The sessions recorded for an assignment, with the usernames of the
+/// students who recorded them, oldest first. Transcripts aren't loaded.
+pub fn load_assignment_sessions(assignment_id: &usize) -> E<Vec<(SessionData, Option<String>)>> {
+    let connection = DB.lock().unwrap();
+    let mut statement = connection.prepare(&format!(
+        "SELECT {}, (SELECT username FROM users WHERE users.id = sessions.user_id) \
+         FROM sessions WHERE assignment_id = ?1 ORDER BY created_at",
+        SESSION_COLUMNS
+    ))?;
+    let sessions = statement
+        .query_map(params![assignment_id], |row| {
+            Ok((session_from_row(row)?, row.get(15)?))
+        })?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+    Ok(sessions)
+}
+
+pub fn insert_segment(session_id: usize, response: &TranslationResponse) -> E<()> {
+    DB.lock().unwrap().execute(
+        "INSERT OR REPLACE INTO segments (session_id, sequence_number, segment_number, \
+         num_segments, segment_start, segment_end, translation) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            session_id,
+            response.sequence_number,
+            response.segment_number,
+            response.num_segments,
+            response.segment_start,
+            response.segment_end,
+            response.translation,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Records where in the recording the audio of a sequence starts. Segment
+/// times are relative to the start of their sequence.
+pub fn insert_sequence_start(session_id: &usize, sequence_number: usize, start_ms: i64) -> E<()> {
+    DB.lock().unwrap().execute(
+        "INSERT OR REPLACE INTO sequences (session_id, sequence_number, start_ms) \
+         VALUES (?1, ?2, ?3)",
+        params![session_id, sequence_number, start_ms],
+    )?;
+    Ok(())
+}
+
+pub fn load_sequence_starts(session_id: &usize) -> E<HashMap<usize, i64>> {
+    let connection = DB.lock().unwrap();
+    let mut statement = connection
+        .prepare("SELECT sequence_number, start_ms FROM sequences WHERE session_id = ?1")?;
+    let starts = statement
+        .query_map(params![session_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<HashMap<_, _>, rusqlite::Error>>()?;
+    Ok(starts)
+}
+
+pub fn find_session_id(uuid: &str) -> E<Option<usize>> {
+    let id = DB
+        .lock()
+        .unwrap()
+        .query_row(
+            "SELECT id FROM sessions WHERE uuid = ?1",
+            params![uuid],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(id)
+}
+
+pub fn max_session_id() -> E<usize> {
+    let id: Option<usize> =
+        DB.lock()
+            .unwrap()
+            .query_row("SELECT MAX(id) FROM sessions", [], |row| row.get(0))?;
+    Ok(id.unwrap_or(0))
+}
+
+/// Loads a session, in whatever state, together with the segments
+/// transcribed so far.
+pub fn load_session(id: &usize) -> E<Option<SessionData>> {
+    let connection = DB.lock().unwrap();
+    let session = connection
+        .query_row(
+            &format!("SELECT {} FROM sessions WHERE id = ?1", SESSION_COLUMNS),
+            params![id],
+            session_from_row,
+        )
+        .optional()?;
+    let session = match session {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+    let mut statement = connection.prepare(
+        "SELECT sequence_number, segment_number, num_segments, segment_start, segment_end, \
+         translation FROM segments WHERE session_id = ?1 \
+         ORDER BY sequence_number, segment_number",
+    )?;
+    let segments = statement.query_map(params![id], |row| {
+        Ok(TranslationResponse {
+            sequence_number: row.get(0)?,
+            segment_number: row.get(1)?,
+            num_segments: row.get(2)?,
+            segment_start: row.get(3)?,
+            segment_end: row.get(4)?,
+            translation: row.get(5)?,
+            uuid: session.uuid.to_string(),
+        })
+    })?;
+    {
+        let mut translations = session.translations.lock().unwrap();
+        for segment in segments {
+            translations.add_translation(&segment?)?;
+        }
+    }
+    Ok(Some(session))
+}
+
+/// Loads all sessions belonging to the user, or the anonymous sessions if
+/// `user_id` is `None`, oldest first. The transcripts are not loaded, use
+/// `load_session` for that.
+pub fn load_sessions(user_id: Option<usize>) -> E<Vec<SessionData>> {
+    let connection = DB.lock().unwrap();
+    let mut statement = connection.prepare(&format!(
+        "SELECT {} FROM sessions WHERE user_id IS ?1 ORDER BY created_at",
+        SESSION_COLUMNS
+    ))?;
+    let sessions = statement
+        .query_map(params![user_id], session_from_row)?
+        .collect::<Result<Vec<SessionData>, rusqlite::Error>>()?;
+    Ok(sessions)
+}
+
+/// The ids of the sessions not updated since `before` and not starred.
+pub fn expired_sessions(before: DateTime<Utc>) -> E<Vec<usize>> {
+    let connection = DB.lock().unwrap();
+    let mut statement =
+        connection.prepare("SELECT id FROM sessions WHERE updated_at < ?1 AND starred = 0")?;
+    let ids = statement
+        .query_map(params![before], |row| row.get(0))?
+        .collect::<Result<Vec<usize>, rusqlite::Error>>()?;
+    Ok(ids)
+}
+
+pub fn save_comparison(
+    session_id: usize,
+    resource: &str,
+    lang: &str,
+    translation_count: usize,
+    changes: &[Change],
+) -> E<()> {
+    DB.lock().unwrap().execute(
+        "INSERT OR REPLACE INTO comparisons (session_id, resource, lang, translation_count, \
+         changes, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            session_id,
+            resource,
+            lang,
+            translation_count,
+            serde_json::to_string(changes)?,
+            Utc::now(),
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn insert_annotation(
+    session_id: usize,
+    author_id: Option<usize>,
+    annotation: &AnnotationRequest,
+) -> E<usize> {
+    let connection = DB.lock().unwrap();
+    connection.execute(
+        "INSERT INTO annotations (session_id, author_id, start_time, end_time, side, quote, \
+         comment, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8)",
+        params![
+            session_id,
+            author_id,
+            annotation.start_time,
+            annotation.end_time,
+            annotation.side,
+            annotation.quote,
+            annotation.comment,
+            Utc::now(),
+        ],
+    )?;
+    Ok(connection.last_insert_rowid() as usize)
+}
+
+pub fn update_annotation(id: &usize, annotation: &AnnotationRequest) -> E<()> {
+    DB.lock().unwrap().execute(
+        "UPDATE annotations SET start_time = ?2, end_time = ?3, side = ?4, quote = ?5, \
+         comment = ?6, updated_at = ?7 WHERE id = ?1",
+        params![
+            id,
+            annotation.start_time,
+            annotation.end_time,
+            annotation.side,
+            annotation.quote,
+            annotation.comment,
+            Utc::now(),
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn delete_annotation(id: &usize) -> E<()> {
+    DB.lock()
+        .unwrap()
+        .execute("DELETE FROM annotations WHERE id = ?1", params![id])?;
+    Ok(())
+}
+
+const ANNOTATION_COLUMNS: &str = "id, author_id, \
+                                  (SELECT username FROM users WHERE users.id = author_id), \
+                                  start_time, end_time, side, quote, comment, created_at, \
+                                  updated_at";
+
+fn annotation_from_row(row: &Row) -> rusqlite::Result<Annotation> {
+    Ok(Annotation {
+        id: row.get(0)?,
+        author_id: row.get(1)?,
+        author: row.get(2)?,
+        start_time: row.get(3)?,
+        end_time: row.get(4)?,
+        side: row.get(5)?,
+        quote: row.get(6)?,
+        comment: row.get(7)?,
+        created_at: row.get(8)?,
+        updated_at: row.get(9)?,
+    })
+}
+
+pub fn load_annotation(session_id: &usize, id: &usize) -> E<Option<Annotation>> {
+    let annotation = DB
+        .lock()
+        .unwrap()
+        .query_row(
+            &format!(
+                "SELECT {} FROM annotations WHERE session_id = ?1 AND id = ?2",
+                ANNOTATION_COLUMNS
+            ),
+            params![session_id, id],
+            annotation_from_row,
+        )
+        .optional()?;
+    Ok(annotation)
+}
+
+/// A session's annotations, in the order they occur in the recording.
+/// Those anchored only to the text come last.
+pub fn load_annotations(session_id: &usize) -> E<Vec<Annotation>> {
+    let connection = DB.lock().unwrap();
+    let mut statement = connection.prepare(&format!(
+        "SELECT {} FROM annotations WHERE session_id = ?1 \
+         ORDER BY start_time IS NULL, start_time, created_at",
+        ANNOTATION_COLUMNS
+    ))?;
+    let annotations = statement
+        .query_map(params![session_id], annotation_from_row)?
+        .collect::<Result<Vec<Annotation>, rusqlite::Error>>()?;
+    Ok(annotations)
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    CreatedAt,
+    UpdatedAt,
+    Language,
+    Resource,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Filters, sorting and paging for `query_sessions`. `page` starts at 1.
+/// Only sessions belonging to `user_id` are returned, or the anonymous
+/// sessions if it is `None`, unless `all_users` is set.
+#[derive(Debug, Deserialize)]
+pub struct SessionQuery {
+    #[serde(skip)]
+    pub user_id: Option<usize>,
+    #[serde(skip)]
+    pub all_users: bool,
+    pub resource: Option<String>,
+    pub language: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub state: Option<SessionState>,
+    #[serde(default)]
+    pub sort: SortField,
+    #[serde(default)]
+    pub order: SortOrder,
+    #[serde(default = "default_page")]
+    pub page: usize,
+    #[serde(default = "default_per_page")]
+    pub per_page: usize,
+}
+
+impl SessionQuery {
+    /// How many sessions come before the page, or `None` if that is more
+    /// than SQLite can skip.
+    pub fn offset(&self) -> Option<i64> {
+        self.page
+            .saturating_sub(1)
+            .checked_mul(self.per_page)
+            .and_then(|offset| i64::try_from(offset).ok())
+    }
+}
+
+fn default_page() -> usize {
+    1
+}
+
+fn default_per_page() -> usize {
+    50
+}
+
+pub const MAX_PER_PAGE: usize = 500;
+
+/// Returns one page of the sessions matching `query`, and the total number
+/// of matching sessions. As with `load_sessions`, transcripts aren't loaded.
+pub fn query_sessions(query: &SessionQuery) -> E<(Vec<SessionData>, usize)> {
+    let mut conditions: Vec<&str> = vec![];
+    let mut values: Vec<Box<dyn ToSql>> = vec![];
+    if !query.all_users {
+        conditions.push("user_id IS ?");
+        values.push(Box::new(query.user_id));
+    }
+    if let Some(resource) = &query.resource {
+        conditions.push("resource = ?");
+        values.push(Box::new(resource.clone()));
+    }
+    if let Some(language) = &query.language {
+        conditions.push("language = ?");
+        values.push(Box::new(language.clone()));
+    }
+    if let Some(from) = query.from {
+        conditions.push("created_at >= ?");
+        values.push(Box::new(from));
+    }
+    if let Some(to) = query.to {
+        conditions.push("created_at < ?");
+        values.push(Box::new(to));
+    }
+    if let Some(state) = query.state {
+        conditions.push("state = ?");
+        values.push(Box::new(state));
+    }
+    let where_clause = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!(" WHERE {}", conditions.join(" AND "))
+    };
+    let sort = match query.sort {
+        SortField::CreatedAt => "created_at",
+        SortField::UpdatedAt => "updated_at",
+        SortField::Language => "language",
+        SortField::Resource => "resource",
+    };
+    let order = match query.order {
+        SortOrder::Asc => "ASC",
+        SortOrder::Desc => "DESC",
+    };
+
+    let connection = DB.lock().unwrap();
+    let total: usize = connection.query_row(
+        &format!("SELECT COUNT(*) FROM sessions{}", where_clause),
+        params_from_iter(values.iter()),
+        |row| row.get(0),
+    )?;
+    values.push(Box::new(query.per_page));
+    values.push(Box::new(query.offset().ok_or_else(|| {
+        Er::new(format!("Page {} is out of range", query.page))
+    })?));
+    let mut statement = connection.prepare(&format!(
+        "SELECT {} FROM sessions{} ORDER BY {} {}, id {} LIMIT ? OFFSET ?",
+        SESSION_COLUMNS, where_clause, sort, order, order
+    ))?;
+    let sessions = statement
+        .query_map(params_from_iter(values.iter()), session_from_row)?
+        .collect::<Result<Vec<SessionData>, rusqlite::Error>>()?;
+    Ok((sessions, total))
+}
+
+#[derive(Serialize)]
+pub struct StoredComparison {
+    pub resource: String,
+    pub lang: String,
+    pub translation_count: usize,
+    pub changes: Vec<Change>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub fn load_comparisons(session_id: &usize) -> E<Vec<StoredComparison>> {
+    let connection = DB.lock().unwrap();
+    let mut statement = connection.prepare(
+        "SELECT resource, lang, translation_count, changes, created_at FROM comparisons \
+         WHERE session_id = ?1 ORDER BY created_at",
+    )?;
+    let rows = statement
+        .query_map(params![session_id], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get::<_, String>(3)?,
+                row.get(4)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+    let mut comparisons = vec![];
+    for (resource, lang, translation_count, changes, created_at) in rows {
+        comparisons.push(StoredComparison {
+            resource,
+            lang,
+            translation_count,
+            changes: serde_json::from_str(&changes)?,
+            created_at,
+        });
+    }
+    Ok(comparisons)
+}
+
+fn session_from_row(row: &Row) -> rusqlite::Result<SessionData> {
+    let uuid: String = row.get(1)?;
+    let uuid = Uuid::parse_str(&uuid).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, Box::new(e))
+    })?;
+    let sequence_number: usize = row.get(5)?;
+    let recording_file: Option<String> = row.get(6)?;
+    Ok(SessionData {
+        id: row.get(0)?,
+        transcription_sender_tx: None,
+        language: row.get(2)?,
+        uuid,
+        resource: row.get(3)?,
+        sample_rate: row.get(4)?,
+        valid: false,
+        buffer: vec![],
+        silence_length: 0,
+        sent_samples: 0,
+        compared_sequence: None,
+        sequence_number,
+        last_sequence: Some(sequence_number.saturating_sub(1)),
+        recording_file,
+        transcript_file: row.get(7)?,
+        segments_file: row.get(8)?,
+        translations: Arc::new(Mutex::new(TranslationResponses::new())),
+        created_at: row.get(9)?,
+        updated_at: row.get(10)?,
+        starred: row.get(11)?,
+        state: row.get(12)?,
+        user_id: row.get(13)?,
+        assignment_id: row.get(14)?,
+    })
+}
+
+impl ToSql for SessionState {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for SessionState {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "live" => Ok(SessionState::Live),
+            "transcribing" => Ok(SessionState::Transcribing),
+            "finished" => Ok(SessionState::Finished),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for Side {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for Side {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "transcript" => Ok(Side::Transcript),
+            "reference" => Ok(Side::Reference),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open() -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch("PRAGMA foreign_keys = ON;")
+            .unwrap();
+        connection
+    }
+
+    fn version(connection: &Connection) -> usize {
+        connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn new_databases() {
+        let mut connection = open();
+        migrate(&mut connection).unwrap();
+        assert_eq!(version(&connection), MIGRATIONS.len());
+        connection
+            .prepare(&format!("SELECT {} FROM sessions", SESSION_COLUMNS))
+            .unwrap();
+        // nothing is done a second time.
+        migrate(&mut connection).unwrap();
+        assert_eq!(version(&connection), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn newer_databases_are_refused() {
+        let mut connection = open();
+        connection
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        assert!(migrate(&mut connection).is_err());
+    }
+
+    #[test]
+    fn page_offsets() {
+        let query = |page, per_page| SessionQuery {
+            user_id: None,
+            all_users: false,
+            resource: None,
+            language: None,
+            from: None,
+            to: None,
+            state: None,
+            sort: SortField::default(),
+            order: SortOrder::default(),
+            page,
+            per_page,
+        };
+        assert_eq!(query(1, 50).offset(), Some(0));
+        assert_eq!(query(3, 50).offset(), Some(100));
+        assert_eq!(query(usize::MAX, 500).offset(), None);
+        assert_eq!(query(usize::MAX / 2, 2).offset(), None);
+    }
+}
@@ -0,0 +1,319 @@
+use serde::Serialize;
+
+use crate::normalize::Normalization;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntityKind {
+    Number,
+    Name,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntityError {
+    /// In the reference but not the transcript.
+    Missed,
+    /// Rendered as something else.
+    Wrong,
+    /// In the transcript but not the reference.
+    Added,
+}
+
+/// A number or name found in a text.
+#[derive(Clone, Debug, Serialize)]
+pub struct Entity {
+    pub text: String,
+    /// The index of its first word in the text, with numbers written as
+    /// words counted as one word.
+    pub position: usize,
+    /// The words around it.
+    pub context: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct EntityProblem {
+    pub kind: EntityKind,
+    pub error: EntityError,
+    pub reference: Option<Entity>,
+    pub transcript: Option<Entity>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct EntityAccuracy {
+    /// Numbers in the reference, and how many the transcript has right.
+    pub numbers: usize,
+    pub numbers_correct: usize,
+    /// Names in the reference, and how many the transcript has right.
+    pub names: usize,
+    pub names_correct: usize,
+    pub problems: Vec<EntityProblem>,
+}
+
+/// Words of context given either side of an entity.
+const CONTEXT_WORDS: usize = 3;
+
+/// Words after which a capitalized German word is a noun rather than a name.
+const GERMAN_DETERMINERS: [&str; 24] = [
+    "der", "die", "das", "den", "dem", "des", "ein", "eine", "einen", "einem", "einer", "eines",
+    "kein", "keine", "keinen", "keinem", "keiner", "unser", "unsere", "unseren", "unserem",
+    "diese", "dieser", "diesen",
+];
+
+/// Endings of German nouns, which are capitalized like names.
+const GERMAN_NOUN_SUFFIXES: [&str; 10] = [
+    "ung", "heit", "keit", "schaft", "tion", "ität", "nis", "tum", "ismus", "ment",
+];
+
+/// A word with the punctuation around it removed.
+fn core(token: &str) -> &str {
+    token.trim_matches(|c: char| !c.is_alphanumeric())
+}
+
+fn context(tokens: &[&str], start: usize, end: usize) -> String {
+    tokens[start.saturating_sub(CONTEXT_WORDS)..(end + CONTEXT_WORDS).min(tokens.len())].join(" ")
+}
+
+/// The number a word is, without ordinal endings like "th" or "e".
+fn number(word: &str) -> Option<&str> {
+    let digits = word.trim_end_matches(char::is_alphabetic);
+    let is_number = digits.starts_with(|c: char| c.is_ascii_digit())
+        && digits
+            .chars()
+            .all(|c| c.is_ascii_digit() || ".,:/".contains(c))
+        && word.len() - digits.len() <= 2;
+    is_number.then_some(digits)
+}
+
+/// Numbers, written in digits or words, and runs of capitalized words which
+/// don't start a sentence.
+fn extract(text: &str, lang: &str) -> Vec<(EntityKind, Entity)> {
+    let normalization = Normalization {
+        numbers: true,
+        ..Default::default()
+    };
+    let text = normalization.apply(text, lang);
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    let mut entities = vec![];
+    let mut i = 0;
+    while i < tokens.len() {
+        let word = core(tokens[i]);
+        if let Some(number) = number(word) {
+            entities.push((
+                EntityKind::Number,
+                Entity {
+                    text: number.to_string(),
+                    position: i,
+                    context: context(&tokens, i, i + 1),
+                },
+            ));
+            i += 1;
+            continue;
+        }
+        let sentence_start = i == 0
+            || (tokens[i - 1].ends_with(['.', '!', '?', ':', '…', '"'])
+                && !is_initial(core(tokens[i - 1])));
+        if sentence_start || !is_name(word) {
+            i += 1;
+            continue;
+        }
+        if lang == "de" {
+            let after_determiner =
+                i > 0 && GERMAN_DETERMINERS.contains(&tokens[i - 1].to_lowercase().as_str());
+            let noun = GERMAN_NOUN_SUFFIXES.iter().any(|s| word.ends_with(s));
+            if after_determiner || noun {
+                i += 1;
+                continue;
+            }
+        }
+        // Names go on over capitalized words and initials, but not past
+        // other punctuation.
+        let start = i;
+        while i + 1 < tokens.len() {
+            let word = core(tokens[i]);
+            let trailing = &tokens[i][tokens[i].find(word).unwrap_or(0) + word.len()..];
+            let next = core(tokens[i + 1]);
+            let joined = trailing.is_empty() || (is_initial(word) && trailing == ".");
+            if !(joined && (is_name(next) || is_initial(next))) {
+                break;
+            }
+            i += 1;
+        }
+        let name: Vec<&str> = tokens[start..=i].iter().map(|t| core(t)).collect();
+        entities.push((
+            EntityKind::Name,
+            Entity {
+                text: name.join(" "),
+                position: start,
+                context: context(&tokens, start, i + 1),
+            },
+        ));
+        i += 1;
+    }
+    entities
+}
+
+fn is_name(word: &str) -> bool {
+    word.chars().count() > 1 && word.starts_with(char::is_uppercase)
+}
+
+fn is_initial(word: &str) -> bool {
+    word.chars().count() == 1 && word.starts_with(char::is_uppercase)
+}
+
+/// Names are the same if all the words of one are in the other, so that
+/// "Kennedy" is "John F Kennedy".
+fn same(kind: EntityKind, a: &str, b: &str) -> bool {
+    match kind {
+        EntityKind::Number => a == b,
+        EntityKind::Name => {
+            let a: Vec<String> = a.split(' ').map(str::to_lowercase).collect();
+            let b: Vec<String> = b.split(' ').map(str::to_lowercase).collect();
+            a.iter().all(|w| b.contains(w)) || b.iter().all(|w| a.contains(w))
+        }
+    }
+}
+
+/// Pairs up the entities of the reference and the transcript in order, as
+/// a longest common subsequence. Between matched pairs, unmatched entities
+/// of the two sides are paired as wrong renderings, and any left over are
+/// missed or added.
+fn match_entities(
+    kind: EntityKind,
+    reference: &[Entity],
+    transcript: &[Entity],
+) -> (usize, Vec<EntityProblem>) {
+    let (n, m) = (reference.len(), transcript.len());
+    let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if same(kind, &reference[i].text, &transcript[j].text) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut correct = 0;
+    let mut problems = vec![];
+    let mut missed = vec![];
+    let mut added = vec![];
+    let mut flush = |missed: &mut Vec<&Entity>, added: &mut Vec<&Entity>| {
+        let wrong = missed.len().min(added.len());
+        for k in 0..missed.len().max(added.len()) {
+            problems.push(EntityProblem {
+                kind,
+                error: if k < wrong {
+                    EntityError::Wrong
+                } else if k < missed.len() {
+                    EntityError::Missed
+                } else {
+                    EntityError::Added
+                },
+                reference: missed.get(k).map(|e| (*e).clone()),
+                transcript: added.get(k).map(|e| (*e).clone()),
+            });
+        }
+        missed.clear();
+        added.clear();
+    };
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && same(kind, &reference[i].text, &transcript[j].text) {
+            flush(&mut missed, &mut added);
+            correct += 1;
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            missed.push(&reference[i]);
+            i += 1;
+        } else {
+            added.push(&transcript[j]);
+            j += 1;
+        }
+    }
+    flush(&mut missed, &mut added);
+    (correct, problems)
+}
+
+/// Checks the numbers and names of the reference are in the transcript.
+pub fn check(reference: &str, transcript: &str, lang: &str) -> EntityAccuracy {
+    let reference = extract(reference, lang);
+    let transcript = extract(transcript, lang);
+    let of_kind = |entities: &[(EntityKind, Entity)], kind| -> Vec<Entity> {
+        entities
+            .iter()
+            .filter(|(k, _)| *k == kind)
+            .map(|(_, e)| e.clone())
+            .collect()
+    };
+    let numbers = of_kind(&reference, EntityKind::Number);
+    let names = of_kind(&reference, EntityKind::Name);
+    let (numbers_correct, mut problems) = match_entities(
+        EntityKind::Number,
+        &numbers,
+        &of_kind(&transcript, EntityKind::Number),
+    );
+    let (names_correct, name_problems) = match_entities(
+        EntityKind::Name,
+        &names,
+        &of_kind(&transcript, EntityKind::Name),
+    );
+    problems.extend(name_problems);
+    EntityAccuracy {
+        numbers: numbers.len(),
+        numbers_correct,
+        names: names.len(),
+        names_correct,
+        problems,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn articles_are_not_entities() {
+        assert!(extract("ein Haus", "de").is_empty());
+        let accuracy = check("Er kaufte ein Haus.", "Er kaufte eine Wohnung.", "de");
+        assert_eq!(accuracy.numbers, 0);
+        assert!(accuracy.problems.is_empty());
+    }
+
+    #[test]
+    fn numbers_written_as_words() {
+        let entities = extract("Es kamen einhundert Gäste.", "de");
+        let numbers: Vec<&str> = entities
+            .iter()
+            .filter(|(kind, _)| *kind == EntityKind::Number)
+            .map(|(_, e)| e.text.as_str())
+            .collect();
+        assert_eq!(numbers, vec!["100"]);
+    }
+
+    #[test]
+    fn wrong_and_missed_numbers() {
+        let accuracy = check(
+            "We spent 40 days and 3 nights there.",
+            "We spent forty-one days there.",
+            "en",
+        );
+        assert_eq!(accuracy.numbers, 2);
+        assert_eq!(accuracy.numbers_correct, 0);
+        let errors: Vec<EntityError> = accuracy.problems.iter().map(|p| p.error).collect();
+        assert_eq!(errors, vec![EntityError::Wrong, EntityError::Missed]);
+    }
+
+    #[test]
+    fn names_match_in_part() {
+        let accuracy = check(
+            "Today we welcome John F. Kennedy here.",
+            "Today we welcome Kennedy here.",
+            "en",
+        );
+        assert_eq!(accuracy.names, 1);
+        assert_eq!(accuracy.names_correct, 1);
+    }
+}
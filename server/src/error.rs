@@ -1,5 +1,11 @@
+use serde_json::json;
 use std::error::Error;
 use std::fmt;
+use warp::http::StatusCode;
+use warp::{Rejection, Reply};
+
+use crate::compare::CompareError;
+use crate::translate::TranslationRequest;
 
 pub type E<T> = Result<T, Box<dyn Error>>;
 
@@ -21,3 +27,188 @@ impl fmt::Display for Er {
         write!(f, "{}", &self.msg)
     }
 }
+
+/// The errors the API replies with. Each has its own status, and is sent as
+/// a JSON body like `{"error": "not_found", "message": "Session not found"}`.
+#[derive(Debug, Clone)]
+pub enum ApiError {
+    NotFound(String),
+    BadRequest(String),
+    /// The request needs a logged in user and hasn't got one, or the login
+    /// failed.
+    Unauthorized(String),
+    /// The user, or token, lacks the scope the route needs.
+    Forbidden(String),
+    MethodNotAllowed,
+    /// The request can't be carried out in the state things are in, like
+    /// deleting a session still being recorded.
+    Conflict(String),
+    PayloadTooLarge,
+    /// The transcription engine can't be reached.
+    EngineUnavailable,
+    /// The database or the files of sessions and resources couldn't be read
+    /// or written. The cause is logged rather than sent to the client.
+    Storage,
+    Internal,
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::EngineUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Storage | ApiError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The `error` field of the body.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::MethodNotAllowed => "method_not_allowed",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::PayloadTooLarge => "payload_too_large",
+            ApiError::EngineUnavailable => "engine_unavailable",
+            ApiError::Storage => "storage",
+            ApiError::Internal => "internal",
+        }
+    }
+
+    /// The reply for the error, with the fields of `details`, if it is an
+    /// object, added to the body.
+    pub fn reply_with(&self, details: serde_json::Value) -> warp::reply::Response {
+        let mut body = json!({
+            "error": self.code(),
+            "message": self.to_string(),
+        });
+        if let serde_json::Value::Object(details) = details {
+            for (key, value) in details {
+                body[key] = value;
+            }
+        }
+        warp::reply::with_status(warp::reply::json(&body), self.status()).into_response()
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::NotFound(msg)
+            | ApiError::BadRequest(msg)
+            | ApiError::Unauthorized(msg)
+            | ApiError::Forbidden(msg)
+            | ApiError::Conflict(msg) => write!(f, "{}", msg),
+            ApiError::MethodNotAllowed => write!(f, "Method not allowed"),
+            ApiError::PayloadTooLarge => write!(f, "Request body too large"),
+            ApiError::EngineUnavailable => write!(f, "Transcription engine unavailable"),
+            ApiError::Storage => write!(f, "Couldn't read or save data"),
+            ApiError::Internal => write!(f, "Internal error"),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl warp::reject::Reject for ApiError {}
+
+impl Reply for ApiError {
+    fn into_response(self) -> warp::reply::Response {
+        self.reply_with(serde_json::Value::Null)
+    }
+}
+
+impl From<&CompareError> for ApiError {
+    fn from(e: &CompareError) -> Self {
+        match e {
+            CompareError::MissingTranslation { .. } => ApiError::BadRequest(e.to_string()),
+            _ => ApiError::NotFound(e.to_string()),
+        }
+    }
+}
+
+/// Sorts the errors from the rest of the server by what went wrong. Only
+/// the kind of error is kept, so callers log the error itself.
+impl From<Box<dyn Error>> for ApiError {
+    fn from(e: Box<dyn Error>) -> Self {
+        if let Some(e) = e.downcast_ref::<ApiError>() {
+            return e.clone();
+        }
+        if let Some(e) = e.downcast_ref::<CompareError>() {
+            return ApiError::from(e);
+        }
+        if let Some(io) = e.downcast_ref::<std::io::Error>() {
+            if io.kind() == std::io::ErrorKind::NotFound {
+                return ApiError::NotFound("File not found".to_string());
+            }
+        }
+        if e.is::<reqwest::Error>() || e.is::<crossbeam_channel::SendError<TranslationRequest>>() {
+            return ApiError::EngineUnavailable;
+        }
+        if e.is::<rusqlite::Error>()
+            || e.is::<std::io::Error>()
+            || e.is::<hound::Error>()
+            || e.is::<zip::result::ZipError>()
+        {
+            return ApiError::Storage;
+        }
+        ApiError::Internal
+    }
+}
+
+/// Rejects a request because of `e`. Comparison errors are kept as they
+/// are, so their details go in the body.
+pub fn reject(e: Box<dyn Error>) -> Rejection {
+    match e.downcast::<CompareError>() {
+        Ok(e) => warp::reject::custom(*e),
+        Err(e) => warp::reject::custom(ApiError::from(e)),
+    }
+}
+
+/// Replies to rejected requests with the JSON body of their error. Warp's
+/// own rejections, for requests which match no route or can't be read, are
+/// given the nearest `ApiError`. The few others, like a websocket request
+/// without an upgrade, are left to warp, as are requests from origins CORS
+/// doesn't allow, which are rejected outside the routes this handles so that
+/// its replies still have CORS headers.
+pub async fn handle_rejection(err: Rejection) -> Result<warp::reply::Response, Rejection> {
+    use warp::reject;
+
+    if let Some(e) = err.find::<CompareError>() {
+        return Ok(ApiError::from(e).reply_with(json!(e)));
+    }
+    let error = if let Some(e) = err.find::<ApiError>() {
+        e.clone()
+    } else if let Some(e) = err.find::<warp::body::BodyDeserializeError>() {
+        ApiError::BadRequest(e.to_string())
+    } else if let Some(e) = err.find::<reject::InvalidQuery>() {
+        ApiError::BadRequest(e.to_string())
+    } else if let Some(e) = err.find::<reject::InvalidHeader>() {
+        ApiError::BadRequest(e.to_string())
+    } else if let Some(e) = err.find::<reject::MissingHeader>() {
+        ApiError::BadRequest(e.to_string())
+    } else if let Some(e) = err.find::<reject::MissingCookie>() {
+        ApiError::BadRequest(e.to_string())
+    } else if let Some(e) = err.find::<reject::LengthRequired>() {
+        ApiError::BadRequest(e.to_string())
+    } else if let Some(e) = err.find::<reject::UnsupportedMediaType>() {
+        ApiError::BadRequest(e.to_string())
+    } else if err.find::<reject::PayloadTooLarge>().is_some() {
+        ApiError::PayloadTooLarge
+    } else if err.find::<reject::MethodNotAllowed>().is_some() {
+        ApiError::MethodNotAllowed
+    } else if err.is_not_found() {
+        ApiError::NotFound("Not found".to_string())
+    } else {
+        return Err(err);
+    };
+    Ok(error.into_response())
+}
@@ -0,0 +1,175 @@
+use futures_util::{stream, Stream, StreamExt};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Mutex;
+use tokio::sync::broadcast::{self, error::RecvError};
+use warp::ws::Message;
+
+use crate::compare::LiveComparison;
+use crate::error::E;
+use crate::session::{SessionData, Status};
+use crate::translate::TranslationResponse;
+
+/// Events a follower can fall behind by before the oldest are dropped.
+const EVENT_BUFFER: usize = 256;
+
+/// Something that happened to a session. Segments and comparisons go to the
+/// interpreter over the websocket, and every event goes to the session's
+/// followers at `/events/:uuid`.
+#[derive(Clone, Debug, Serialize)]
+#[serde(untagged)]
+pub enum SessionEvent {
+    Segment(TranslationResponse),
+    Comparison(LiveComparison),
+    /// The number of sequences sent for transcription or transcribed, or
+    /// the state of the session, has changed.
+    Status(Status),
+    Error {
+        message: String,
+    },
+}
+
+impl SessionEvent {
+    /// The `event` field of the server-sent event.
+    fn name(&self) -> &'static str {
+        match self {
+            SessionEvent::Segment(_) => "segment",
+            SessionEvent::Comparison(_) => "comparison",
+            SessionEvent::Status(_) => "status",
+            SessionEvent::Error { .. } => "error",
+        }
+    }
+
+    /// The message sent over the websocket, if the event is one it carries.
+    fn websocket_message(&self) -> Option<Message> {
+        match self {
+            SessionEvent::Segment(response) => Some(Message::text(json!(response).to_string())),
+            SessionEvent::Comparison(comparison) => Some(Message::text(
+                json!({ "comparison": comparison }).to_string(),
+            )),
+            _ => None,
+        }
+    }
+
+    fn sse(&self) -> warp::sse::Event {
+        warp::sse::Event::default()
+            .event(self.name())
+            .data(json!(self).to_string())
+    }
+}
+
+/// The channel of a session someone is following, or a mark that the
+/// session's events have ended, so that followers who come after that don't
+/// wait for more.
+enum Followers {
+    Open(broadcast::Sender<SessionEvent>),
+    Closed,
+}
+
+lazy_static! {
+    static ref FOLLOWERS: Mutex<HashMap<usize, Followers>> = Mutex::new(HashMap::new());
+}
+
+/// Sends `event` to the session's followers, and over the session's
+/// websocket if it is one the websocket carries.
+pub fn send(session: &SessionData, event: SessionEvent) -> E<()> {
+    let message = event.websocket_message();
+    publish(session.id, event);
+    if let (Some(message), Some(sender)) = (message, session.transcription_sender_tx.as_ref()) {
+        sender.send(message)?;
+    }
+    Ok(())
+}
+
+/// Sends `event` to the session's followers only.
+pub fn publish(session_id: usize, event: SessionEvent) {
+    let mut followers = FOLLOWERS.lock().unwrap();
+    if let Some(Followers::Open(sender)) = followers.get(&session_id) {
+        if sender.send(event).is_err() {
+            // everyone following the session has gone.
+            followers.remove(&session_id);
+        }
+    }
+}
+
+/// Sends the session's followers its status.
+pub fn publish_status(session: &SessionData) {
+    match session.status() {
+        Ok(status) => publish(session.id, SessionEvent::Status(status)),
+        Err(e) => log::error!("Couldn't get status of session {}: {:?}", session.id, e),
+    }
+}
+
+pub fn publish_error(session_id: usize, message: String) {
+    publish(session_id, SessionEvent::Error { message });
+}
+
+/// Ends the streams of the session's followers, once they have had the
+/// events sent so far.
+pub fn close(session_id: usize) {
+    FOLLOWERS
+        .lock()
+        .unwrap()
+        .insert(session_id, Followers::Closed);
+}
+
+/// Forgets a session which has been dropped from memory.
+pub fn forget(session_id: usize) {
+    FOLLOWERS.lock().unwrap().remove(&session_id);
+}
+
+/// A receiver of the session's events, unless they have ended.
+fn subscribe(session_id: usize) -> Option<broadcast::Receiver<SessionEvent>> {
+    let mut followers = FOLLOWERS.lock().unwrap();
+    let followers = followers
+        .entry(session_id)
+        .or_insert_with(|| Followers::Open(broadcast::channel(EVENT_BUFFER).0));
+    match followers {
+        Followers::Open(sender) => Some(sender.subscribe()),
+        Followers::Closed => None,
+    }
+}
+
+/// The session's events as server-sent events, starting with its status.
+/// The stream ends when the session is finished, straight away if it
+/// already is, or if it isn't being recorded or transcribed here, like a
+/// session left unfinished by a restart.
+pub async fn follow(
+    session: &SessionData,
+) -> E<impl Stream<Item = Result<warp::sse::Event, Infallible>>> {
+    let receiver = if crate::session::is_active(&session.id).await {
+        subscribe(session.id)
+    } else {
+        None
+    };
+    // the status from after subscribing, so that no change is missed.
+    let status = match crate::session::get_session(&session.id).await {
+        Some(current) => current.status(),
+        None => session.status(),
+    }?;
+    let session_id = session.id;
+    let events = stream::unfold(receiver, move |receiver| async move {
+        let mut receiver = receiver?;
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                log::warn!(
+                    "Follower of session {} missed {} events",
+                    session_id,
+                    missed
+                );
+                SessionEvent::Error {
+                    message: format!("{} events were missed", missed),
+                }
+            }
+            Err(RecvError::Closed) => return None,
+        };
+        Some((event, Some(receiver)))
+    });
+    Ok(stream::once(async move { SessionEvent::Status(status) })
+        .chain(events)
+        .map(|event| Ok(event.sse())))
+}
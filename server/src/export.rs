@@ -0,0 +1,42 @@
+use serde_json::json;
+use std::io::{Cursor, Write};
+use std::ops::Deref;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+use crate::db;
+use crate::error::E;
+use crate::session::SessionData;
+
+/// Bundles everything kept about a session into a zip file: the recording,
+/// the transcript as text and with its segments, the session metadata, the
+/// comparisons made against it and its annotations.
+pub fn export_session(session: &SessionData) -> E<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default();
+
+    if let Some(filename) = &session.recording_file {
+        if let Ok(audio) = std::fs::read(filename) {
+            zip.start_file(format!("{}.wav", session.uuid), options)?;
+            zip.write_all(&audio)?;
+        }
+    }
+
+    zip.start_file(format!("{}.txt", session.uuid), options)?;
+    zip.write_all(session.transcript()?.as_bytes())?;
+
+    let segments = serde_json::to_vec(session.translations.lock().unwrap().deref())?;
+    zip.start_file(format!("{}.json", session.uuid), options)?;
+    zip.write_all(&segments)?;
+
+    zip.start_file("metadata.json", options)?;
+    zip.write_all(json!(session).to_string().as_bytes())?;
+
+    zip.start_file("comparisons.json", options)?;
+    zip.write_all(&serde_json::to_vec(&db::load_comparisons(&session.id)?)?)?;
+
+    zip.start_file("annotations.json", options)?;
+    zip.write_all(&serde_json::to_vec(&db::load_annotations(&session.id)?)?)?;
+
+    Ok(zip.finish()?.into_inner())
+}
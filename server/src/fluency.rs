@@ -0,0 +1,201 @@
+use serde::Serialize;
+
+use crate::error::E;
+use crate::session::SessionData;
+use crate::translate::SILENCE_AMPLITUDE_THRESHOLD;
+
+/// Silences shorter than this are part of normal articulation, not pauses.
+const MIN_PAUSE_SECONDS: f64 = 0.25;
+/// The length of the windows the recording is measured in.
+const FRAME_MILLISECONDS: usize = 20;
+/// Upper bounds of the pause length buckets, in seconds. Longer pauses go
+/// in a last bucket.
+const PAUSE_BUCKETS: [f64; 4] = [0.5, 1.0, 2.0, 4.0];
+
+/// How smoothly a session was delivered.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Fluency {
+    pub words: usize,
+    /// From the first to the last sound in the recording, in seconds.
+    pub duration: Option<f64>,
+    /// Time spent speaking, without pauses, in seconds.
+    pub speaking_time: Option<f64>,
+    /// Words per minute of `duration`.
+    pub speech_rate: Option<f64>,
+    /// Words per minute of `speaking_time`.
+    pub articulation_rate: Option<f64>,
+    pub pauses: Option<Pauses>,
+    pub filled_pauses: usize,
+    pub filled_pauses_per_minute: Option<f64>,
+    /// Words and phrases said twice in a row, like "the the".
+    pub repetitions: usize,
+    /// Words broken off and started again, like "inter- interpreting".
+    pub false_starts: usize,
+}
+
+/// Silences of at least `MIN_PAUSE_SECONDS` between the first and the last
+/// sound in the recording.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Pauses {
+    pub count: usize,
+    pub per_minute: f64,
+    pub total: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub longest: f64,
+    /// How many pauses are up to 0.5, 1, 2 and 4 seconds long, and longer.
+    pub distribution: [usize; 5],
+}
+
+/// Hesitation sounds, after repeated letters have been collapsed, so that
+/// "ummm" counts as "um".
+fn fillers(lang: &str) -> &'static [&'static str] {
+    match lang {
+        "de" => &["äh", "ähm", "öh", "öhm", "hm", "ehm"],
+        "fr" => &["euh", "heu", "hum", "hm", "bah"],
+        "es" => &["eh", "em", "ehm", "mm", "hm"],
+        _ => &["uh", "um", "er", "erm", "ah", "hm", "mm"],
+    }
+}
+
+pub fn analyse(session: &SessionData) -> E<Fluency> {
+    let transcript = session.transcript()?;
+    let mut fluency = analyse_text(&transcript, &session.language);
+    let recording = match &session.recording_file {
+        Some(f) if std::path::Path::new(f).exists() => f,
+        _ => return Ok(fluency),
+    };
+    let mut reader = hound::WavReader::open(recording)?;
+    let sample_rate = reader.spec().sample_rate;
+    let frame = (sample_rate as usize * FRAME_MILLISECONDS / 1000).max(1);
+    // Recordings can be long, so they are read a frame at a time.
+    let mut silent = vec![];
+    let mut level = 0.0;
+    let mut length = 0;
+    for sample in reader.samples::<f32>() {
+        level += sample?.abs();
+        length += 1;
+        if length == frame {
+            silent.push(level / length as f32 <= SILENCE_AMPLITUDE_THRESHOLD);
+            level = 0.0;
+            length = 0;
+        }
+    }
+    let (duration, pauses) = find_pauses(&silent, frame as f64 / sample_rate as f64);
+    let speaking_time = duration - pauses.iter().sum::<f64>();
+    fluency.duration = Some(duration);
+    fluency.speaking_time = Some(speaking_time);
+    fluency.speech_rate = per_minute(fluency.words, duration);
+    fluency.articulation_rate = per_minute(fluency.words, speaking_time);
+    fluency.filled_pauses_per_minute = per_minute(fluency.filled_pauses, duration);
+    fluency.pauses = Some(pause_statistics(pauses, duration));
+    Ok(fluency)
+}
+
+fn per_minute(count: usize, seconds: f64) -> Option<f64> {
+    if seconds <= 0.0 {
+        return None;
+    }
+    Some(count as f64 * 60.0 / seconds)
+}
+
+/// Counts words, filled pauses, repetitions and false starts.
+fn analyse_text(transcript: &str, lang: &str) -> Fluency {
+    let fillers = fillers(lang);
+    let mut words = vec![];
+    let mut fluency = Fluency::default();
+    for token in transcript.split_whitespace() {
+        let word = crate::metrics::words(token).join("");
+        if word.is_empty() {
+            continue;
+        }
+        let mut collapsed = word.chars().collect::<Vec<_>>();
+        collapsed.dedup();
+        if fillers.contains(&collapsed.iter().collect::<String>().as_str()) {
+            fluency.filled_pauses += 1;
+            continue;
+        }
+        if token.ends_with(['-', '–', '—', '…']) || token.ends_with("...") {
+            fluency.false_starts += 1;
+        }
+        words.push(word);
+    }
+    fluency.words = words.len();
+    fluency.repetitions = repetitions(&words);
+    fluency
+}
+
+/// Counts runs of up to three words immediately said again.
+fn repetitions(words: &[String]) -> usize {
+    let mut count = 0;
+    let mut i = 0;
+    while i < words.len() {
+        let repeated = (1..=3)
+            .find(|n| i + 2 * n <= words.len() && words[i..i + n] == words[i + n..i + 2 * n]);
+        match repeated {
+            Some(n) => {
+                count += 1;
+                i += n;
+            }
+            None => i += 1,
+        }
+    }
+    count
+}
+
+/// The time from the first to the last sound, and the lengths of the
+/// pauses in between, in seconds, given which frames of the recording are
+/// silent.
+fn find_pauses(silent: &[bool], frame_seconds: f64) -> (f64, Vec<f64>) {
+    let first = silent.iter().position(|s| !s);
+    let last = silent.iter().rposition(|s| !s);
+    let (first, last) = match (first, last) {
+        (Some(f), Some(l)) => (f, l),
+        _ => return (0.0, vec![]),
+    };
+    let mut pauses = vec![];
+    let mut run = 0;
+    for is_silent in &silent[first..=last] {
+        if *is_silent {
+            run += 1;
+            continue;
+        }
+        let length = run as f64 * frame_seconds;
+        if length >= MIN_PAUSE_SECONDS {
+            pauses.push(length);
+        }
+        run = 0;
+    }
+    ((last - first + 1) as f64 * frame_seconds, pauses)
+}
+
+fn pause_statistics(mut pauses: Vec<f64>, duration: f64) -> Pauses {
+    if pauses.is_empty() {
+        return Pauses::default();
+    }
+    pauses.sort_by(f64::total_cmp);
+    let mut distribution = [0; 5];
+    for pause in &pauses {
+        let bucket = PAUSE_BUCKETS
+            .iter()
+            .position(|bound| pause <= bound)
+            .unwrap_or(PAUSE_BUCKETS.len());
+        distribution[bucket] += 1;
+    }
+    let total: f64 = pauses.iter().sum();
+    let middle = pauses.len() / 2;
+    let median = if pauses.len().is_multiple_of(2) {
+        (pauses[middle - 1] + pauses[middle]) / 2.0
+    } else {
+        pauses[middle]
+    };
+    Pauses {
+        count: pauses.len(),
+        per_minute: per_minute(pauses.len(), duration).unwrap_or(0.0),
+        total,
+        mean: total / pauses.len() as f64,
+        median,
+        longest: pauses[pauses.len() - 1],
+        distribution,
+    }
+}
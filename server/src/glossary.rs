@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::compare::CompareError;
+use crate::error::E;
+use crate::metadata::Metadata;
+
+/// A glossary entry: a term of the resource's native language and the
+/// renderings accepted for it in each target language.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Term {
+    pub term: String,
+    pub renderings: HashMap<String, Vec<String>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TermCheck {
+    pub term: String,
+    /// Whether the term is in the resource's transcript, or `None` if the
+    /// resource has no transcript.
+    pub in_source: Option<bool>,
+    pub renderings: Vec<String>,
+    /// The first accepted rendering found in the transcript of the session.
+    pub rendered_as: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Terminology {
+    pub terms: Vec<TermCheck>,
+    /// Terms in the source, or all of them if there is no transcript.
+    pub expected: usize,
+    /// Expected terms with an accepted rendering in the session.
+    pub rendered: usize,
+    pub coverage: f64,
+}
+
+/// Reads the resource's glossary, a JSON array of objects like
+/// `{"term": "inauguration", "renderings": {"de": ["Amtseinführung"]}}`.
+pub fn load(metadata: &Metadata, resource_path: &str) -> E<Vec<Term>> {
+    let filename = match &metadata.glossary {
+        Some(f) => f,
+        None => {
+            return Err(Box::new(CompareError::MissingGlossary {
+                resource: resource_path.to_string(),
+            }))
+        }
+    };
+    let contents =
+        std::fs::read_to_string(format!("{}/{}", metadata.enclosing_directory, filename))?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+/// Inflectional endings, longest first, which are removed before words are
+/// compared so that "Amtseinführungen" matches "Amtseinführung".
+fn suffixes(lang: &str) -> &'static [&'static str] {
+    match lang {
+        "de" => &["ern", "en", "er", "es", "em", "e", "n", "s"],
+        "fr" => &["es", "e", "s", "x"],
+        "es" => &["es", "as", "os", "a", "o", "s"],
+        _ => &["ing", "ed", "es", "s"],
+    }
+}
+
+/// Words shorter than this after removing an ending are left whole.
+const MIN_STEM_LENGTH: usize = 3;
+
+fn stem(word: &str, lang: &str) -> String {
+    for suffix in suffixes(lang) {
+        if let Some(stem) = word.strip_suffix(suffix) {
+            if stem.chars().count() >= MIN_STEM_LENGTH {
+                return stem.to_string();
+            }
+        }
+    }
+    word.to_string()
+}
+
+/// The words of a text, each with its stem.
+fn stems(text: &str, lang: &str) -> Vec<(String, String)> {
+    crate::metrics::words(text)
+        .into_iter()
+        .map(|w| {
+            let stem = stem(&w, lang);
+            (w, stem)
+        })
+        .collect()
+}
+
+/// Words match if they or their stems are the same, or one is the stem of
+/// the other, as "addresses" and "address" are although "address" loses
+/// its last letter to stemming.
+fn same_word(a: &(String, String), b: &(String, String)) -> bool {
+    a.0 == b.0 || a.1 == b.1 || a.1 == b.0 || a.0 == b.1
+}
+
+/// Whether the words of `phrase` occur together in `text`.
+fn contains(text: &[(String, String)], phrase: &[(String, String)]) -> bool {
+    !phrase.is_empty()
+        && text
+            .windows(phrase.len())
+            .any(|w| w.iter().zip(phrase).all(|(a, b)| same_word(a, b)))
+}
+
+/// Checks for each term of the glossary with renderings into `lang` whether
+/// it is in `source` and whether the interpretation `transcript` has one
+/// of its renderings.
+pub fn check(
+    terms: &[Term],
+    source: Option<&str>,
+    source_lang: &str,
+    transcript: &str,
+    lang: &str,
+) -> Terminology {
+    let source = source.map(|s| stems(s, source_lang));
+    let transcript = stems(transcript, lang);
+    let terms: Vec<TermCheck> = terms
+        .iter()
+        .filter_map(|term| {
+            let renderings = term.renderings.get(lang)?;
+            let in_source = source
+                .as_ref()
+                .map(|s| contains(s, &stems(&term.term, source_lang)));
+            let rendered_as = renderings
+                .iter()
+                .find(|r| contains(&transcript, &stems(r, lang)))
+                .cloned();
+            Some(TermCheck {
+                term: term.term.clone(),
+                in_source,
+                renderings: renderings.clone(),
+                rendered_as,
+            })
+        })
+        .collect();
+    let expected: Vec<&TermCheck> = terms
+        .iter()
+        .filter(|t| t.in_source != Some(false))
+        .collect();
+    let rendered = expected.iter().filter(|t| t.rendered_as.is_some()).count();
+    Terminology {
+        expected: expected.len(),
+        rendered,
+        coverage: if expected.is_empty() {
+            0.0
+        } else {
+            rendered as f64 / expected.len() as f64
+        },
+        terms,
+    }
+}
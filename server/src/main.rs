@@ -1,10 +1,27 @@
+#![recursion_limit = "256"]
+
+mod annotation;
 mod api;
+mod assignment;
+mod auth;
 mod compare;
+mod db;
+mod entities;
 mod error;
+mod events;
+mod export;
+mod fluency;
+mod glossary;
 mod metadata;
+mod metrics;
+mod normalize;
 mod queue;
+mod retention;
 mod session;
+mod share;
+mod timed;
 mod translate;
+mod webhook;
 mod whispercpp;
 mod whisperx;
 
@@ -24,6 +41,14 @@ async fn main() {
 
     env_logger::init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let [command, username] = args.as_slice() {
+        if command == "create-user" {
+            create_user(username);
+            return;
+        }
+    }
+
     let (_translate_tx, translate_rx) = unbounded();
     log::debug!("Making transcription pool");
     whispercpp::start_translate_pool().unwrap();
@@ -41,10 +66,32 @@ async fn main() {
         });
         log::debug!("Started remote whisper process");
     }
+    crate::db::init().unwrap();
     log::info!("Restoring old sessions");
     crate::session::restore_sessions().await.unwrap();
+    tokio::spawn(retention::run(retention::RetentionPolicy::from_env().unwrap()));
+    webhook::init(webhook::WebhookConfig::from_env().unwrap());
 
     std::thread::spawn(move || async { queue::get_queue().queue_process(translate_rx).await });
     log::debug!("Made enqueuing process");
     serve().await;
 }
+
+/// Makes an account from the command line, with the password read from
+/// standard input. Teachers and admins can only be made like this.
+fn create_user(username: &str) {
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password).unwrap();
+    let credentials = auth::Credentials {
+        username: username.to_string(),
+        password: password.trim_end_matches(['\r', '\n']).to_string(),
+    };
+    crate::db::init().unwrap();
+    match auth::create_user(&credentials) {
+        Ok(user) => println!("Created user {} with scopes {:?}", user.username, user.scopes),
+        Err(e) => {
+            eprintln!("Couldn't create user {}: {}", username, e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -16,12 +16,34 @@ pub struct Metadata {
     pub skip: u32,
     pub native: String,
     pub transcript: Option<String>,
-    pub translations: HashMap<String, String>,
+    pub translations: HashMap<String, References>,
+    /// Translations with timestamps, as SubRip, WebVTT or JSON files.
+    #[serde(default)]
+    pub timed_translations: HashMap<String, String>,
+    /// Terms of the native language and their accepted renderings.
+    pub glossary: Option<String>,
     #[serde(skip_serializing)]
     #[serde(skip_deserializing)]
     pub enclosing_directory: String,
 }
 
+/// The reference translation into a language, or several acceptable ones.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum References {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl References {
+    pub fn files(&self) -> &[String] {
+        match self {
+            References::One(file) => std::slice::from_ref(file),
+            References::Many(files) => files,
+        }
+    }
+}
+
 impl Metadata {
     pub fn from_filename(filename: String) -> E<Self> {
         let f = std::fs::File::open(&filename)?;
@@ -52,4 +74,8 @@ impl Metadata {
         metadata.enclosing_directory = full_path;
         Ok(metadata)
     }
+
+    pub fn has_translation(&self, lang: &str) -> bool {
+        self.translations.contains_key(lang) || self.timed_translations.contains_key(lang)
+    }
 }
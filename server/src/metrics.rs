@@ -0,0 +1,297 @@
+use serde::Serialize;
+use similar::{capture_diff_slices, Algorithm, DiffOp};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Numbers describing how close a transcript is to the reference
+/// translation. Word level metrics ignore case and punctuation.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Metrics {
+    /// Word error rate: substitutions, deletions and insertions needed to
+    /// turn the transcript into the reference, per reference word.
+    pub wer: f64,
+    /// The proportion of transcript words which are in the reference.
+    pub precision: f64,
+    /// The proportion of reference words which are in the transcript.
+    pub recall: f64,
+    /// Character n-gram F-score, with n up to 6 and recall weighted twice
+    /// as much as precision.
+    pub chrf: f64,
+    /// BLEU with n-grams up to 4, smoothed so that short transcripts don't
+    /// score zero.
+    pub bleu: f64,
+    /// Runs of reference words missing from the transcript.
+    pub omissions: usize,
+    pub omitted_words: usize,
+    /// Runs of transcript words not in the reference.
+    pub additions: usize,
+    pub added_words: usize,
+    pub substitutions: usize,
+    pub transcript_words: usize,
+    pub reference_words: usize,
+}
+
+const CHRF_ORDER: usize = 6;
+const CHRF_BETA: f64 = 2.0;
+const BLEU_ORDER: usize = 4;
+
+pub fn words(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .map(|w| {
+            w.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .filter(|w| !w.is_empty())
+        .collect()
+}
+
+pub fn compute(transcript: &str, reference: &str) -> Metrics {
+    let hypothesis = words(transcript);
+    let reference_words = words(reference);
+    let mut metrics = Metrics {
+        transcript_words: hypothesis.len(),
+        reference_words: reference_words.len(),
+        wer: word_error_rate(&hypothesis, &reference_words),
+        chrf: chrf(transcript, reference),
+        bleu: bleu(&hypothesis, &reference_words),
+        ..Default::default()
+    };
+
+    let matches = clipped_matches(&counts(&hypothesis), &counts(&reference_words));
+    metrics.precision = ratio(matches, hypothesis.len());
+    metrics.recall = ratio(matches, reference_words.len());
+
+    for op in capture_diff_slices(Algorithm::Myers, &hypothesis, &reference_words) {
+        match op {
+            DiffOp::Insert { new_len, .. } => {
+                metrics.omissions += 1;
+                metrics.omitted_words += new_len;
+            }
+            DiffOp::Delete { old_len, .. } => {
+                metrics.additions += 1;
+                metrics.added_words += old_len;
+            }
+            DiffOp::Replace {
+                old_len, new_len, ..
+            } => {
+                // words replaced by more or fewer words: the extra ones
+                // are added or omitted.
+                let substituted = old_len.min(new_len);
+                metrics.substitutions += substituted;
+                if new_len > substituted {
+                    metrics.omissions += 1;
+                    metrics.omitted_words += new_len - substituted;
+                }
+                if old_len > substituted {
+                    metrics.additions += 1;
+                    metrics.added_words += old_len - substituted;
+                }
+            }
+            DiffOp::Equal { .. } => (),
+        }
+    }
+    metrics
+}
+
+/// The Dice coefficient of the words of two texts: twice the number of
+/// words they share, divided by the number of words in both.
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let a = words(a);
+    let b = words(b);
+    ratio(
+        2 * clipped_matches(&counts(&a), &counts(&b)),
+        a.len() + b.len(),
+    )
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 {
+        return 0.0;
+    }
+    numerator as f64 / denominator as f64
+}
+
+/// Levenshtein distance over words, divided by the reference length.
+fn word_error_rate(hypothesis: &[String], reference: &[String]) -> f64 {
+    if reference.is_empty() {
+        return if hypothesis.is_empty() { 0.0 } else { 1.0 };
+    }
+    let mut previous: Vec<usize> = (0..=reference.len()).collect();
+    let mut current = vec![0; reference.len() + 1];
+    for (i, h) in hypothesis.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, r) in reference.iter().enumerate() {
+            let substitution = previous[j] + usize::from(h != r);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[reference.len()] as f64 / reference.len() as f64
+}
+
+fn counts<T: Eq + Hash + Clone>(items: &[T]) -> HashMap<T, usize> {
+    let mut counts = HashMap::new();
+    for item in items {
+        *counts.entry(item.clone()).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn ngrams<T: Eq + Hash + Clone>(items: &[T], n: usize) -> HashMap<Vec<T>, usize> {
+    if items.len() < n {
+        return HashMap::new();
+    }
+    counts(&items.windows(n).map(|w| w.to_vec()).collect::<Vec<_>>())
+}
+
+/// How many items of the hypothesis are in the reference, counting each
+/// reference item at most as often as it occurs there.
+fn clipped_matches<T: Eq + Hash>(
+    hypothesis: &HashMap<T, usize>,
+    reference: &HashMap<T, usize>,
+) -> usize {
+    hypothesis
+        .iter()
+        .map(|(item, count)| (*count).min(*reference.get(item).unwrap_or(&0)))
+        .sum()
+}
+
+fn chrf(transcript: &str, reference: &str) -> f64 {
+    let hypothesis: Vec<char> = transcript.chars().filter(|c| !c.is_whitespace()).collect();
+    let reference: Vec<char> = reference.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut precision = 0.0;
+    let mut recall = 0.0;
+    let mut orders = 0;
+    for n in 1..=CHRF_ORDER {
+        let h = ngrams(&hypothesis, n);
+        let r = ngrams(&reference, n);
+        let h_total: usize = h.values().sum();
+        let r_total: usize = r.values().sum();
+        if h_total == 0 || r_total == 0 {
+            continue;
+        }
+        let matches = clipped_matches(&h, &r);
+        precision += ratio(matches, h_total);
+        recall += ratio(matches, r_total);
+        orders += 1;
+    }
+    if orders == 0 {
+        return 0.0;
+    }
+    precision /= orders as f64;
+    recall /= orders as f64;
+    let beta2 = CHRF_BETA * CHRF_BETA;
+    if precision + recall == 0.0 {
+        return 0.0;
+    }
+    (1.0 + beta2) * precision * recall / (beta2 * precision + recall)
+}
+
+/// BLEU with add-one smoothing of the precisions of n-grams longer than one
+/// word.
+fn bleu(hypothesis: &[String], reference: &[String]) -> f64 {
+    if hypothesis.is_empty() || reference.is_empty() {
+        return 0.0;
+    }
+    let mut log_precision = 0.0;
+    for n in 1..=BLEU_ORDER {
+        let h = ngrams(hypothesis, n);
+        let total: usize = h.values().sum();
+        let matches = clipped_matches(&h, &ngrams(reference, n));
+        let precision = if n == 1 {
+            ratio(matches, total)
+        } else {
+            (matches + 1) as f64 / (total + 1) as f64
+        };
+        if precision == 0.0 {
+            return 0.0;
+        }
+        log_precision += precision.ln() / BLEU_ORDER as f64;
+    }
+    let brevity_penalty = if hypothesis.len() < reference.len() {
+        (1.0 - reference.len() as f64 / hypothesis.len() as f64).exp()
+    } else {
+        1.0
+    };
+    brevity_penalty * log_precision.exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn word_error_rates() {
+        assert_eq!(compute("The cat sat.", "the cat sat").wer, 0.0);
+        assert!(close(
+            compute("the cat sat", "the cat sat on the mat").wer,
+            0.5
+        ));
+        assert!(close(
+            compute("the dog sat on a mat", "the cat sat on the mat").wer,
+            2.0 / 6.0
+        ));
+        assert!(close(compute("a b c", "x y").wer, 1.5));
+        assert_eq!(compute("words", "").wer, 1.0);
+        assert_eq!(compute("", "").wer, 0.0);
+    }
+
+    #[test]
+    fn bleu_scores() {
+        let reference = "the cat sat on the mat";
+        assert!(close(compute(reference, reference).bleu, 1.0));
+        // every n-gram matches, but it is a third of the length.
+        assert!(close(compute("the cat", reference).bleu, (-2.0f64).exp()));
+        // "the" is clipped to the once it is in the reference.
+        assert!(close(
+            compute("the the the the", "the cat").bleu,
+            (0.25 * 0.25 / 3.0 * 0.5f64).powf(0.25)
+        ));
+        assert_eq!(compute("dog", reference).bleu, 0.0);
+        assert_eq!(compute("", reference).bleu, 0.0);
+    }
+
+    #[test]
+    fn chrf_scores() {
+        assert!(close(compute("the cat", "the cat").chrf, 1.0));
+        // a matches, but b and c and the bigrams don't.
+        assert!(close(compute("ab", "ac").chrf, 0.25));
+        // precision 1, recall (3/4 + 2/3 + 1/2) / 3, beta 2.
+        let recall = (0.75 + 2.0 / 3.0 + 0.5) / 3.0;
+        assert!(close(
+            compute("abc", "abcd").chrf,
+            5.0 * recall / (4.0 + recall)
+        ));
+        assert_eq!(compute("xyz", "abc").chrf, 0.0);
+        assert_eq!(compute("", "abc").chrf, 0.0);
+    }
+
+    #[test]
+    fn omissions_and_additions() {
+        let metrics = compute("the cat sat", "the cat sat on the mat");
+        assert_eq!((metrics.omissions, metrics.omitted_words), (1, 3));
+        assert_eq!((metrics.additions, metrics.added_words), (0, 0));
+
+        let metrics = compute("the big cat sat", "the cat sat");
+        assert_eq!((metrics.additions, metrics.added_words), (1, 1));
+        assert_eq!(metrics.substitutions, 0);
+    }
+
+    #[test]
+    fn replacements_of_different_lengths() {
+        // "dog" stands for "cat", and "on the mat" is missing.
+        let metrics = compute("a dog sat", "a cat on the mat");
+        assert_eq!(metrics.substitutions, 2);
+        assert_eq!((metrics.omissions, metrics.omitted_words), (1, 2));
+        assert_eq!((metrics.additions, metrics.added_words), (0, 0));
+
+        let metrics = compute("a cat on the mat", "a dog sat");
+        assert_eq!(metrics.substitutions, 2);
+        assert_eq!((metrics.additions, metrics.added_words), (1, 2));
+        assert_eq!((metrics.omissions, metrics.omitted_words), (0, 0));
+    }
+}
@@ -0,0 +1,647 @@
+use serde::Deserialize;
+use unicode_normalization::UnicodeNormalization;
+
+/// Ways of making the transcript and reference more alike before they are
+/// compared, so that differences which don't matter aren't shown. Each is
+/// off by default, and selected with query parameters like
+/// `?lowercase=true&punctuation=true`, or all at once with `?all=true`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct Normalization {
+    pub all: bool,
+    /// Unicode NFC, so that composed and decomposed accents are the same.
+    pub nfc: bool,
+    /// Numbers written as words become digits, and thousands separators are
+    /// removed from digits. Articles which are also "one", like "ein", are
+    /// left alone unless they start a longer number.
+    pub numbers: bool,
+    pub lowercase: bool,
+    /// Punctuation is removed, except between digits.
+    pub punctuation: bool,
+    /// Runs of whitespace, including line breaks, become a single space.
+    pub whitespace: bool,
+}
+
+impl Normalization {
+    pub fn is_empty(&self) -> bool {
+        !(self.all
+            || self.nfc
+            || self.numbers
+            || self.lowercase
+            || self.punctuation
+            || self.whitespace)
+    }
+
+    pub fn apply(&self, text: &str, lang: &str) -> String {
+        let mut text = text.to_string();
+        if self.all || self.nfc {
+            text = text.nfc().collect();
+        }
+        if self.all || self.numbers {
+            text = normalize_numbers(&text, lang);
+        }
+        if self.all || self.lowercase {
+            text = text.to_lowercase();
+        }
+        if self.all || self.punctuation {
+            text = strip_punctuation(&text);
+        }
+        if self.all || self.whitespace {
+            text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+        }
+        text
+    }
+}
+
+fn strip_punctuation(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut result = String::with_capacity(text.len());
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_alphanumeric() || c.is_whitespace() {
+            result.push(*c);
+            continue;
+        }
+        let between_digits = i > 0
+            && chars[i - 1].is_ascii_digit()
+            && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit());
+        if between_digits {
+            result.push(*c);
+        }
+    }
+    result
+}
+
+#[derive(Clone, Copy)]
+enum NumberWord {
+    Value(u64),
+    /// Multiplies the number so far, like "hundred".
+    Hundred,
+    /// Ends a group of three digits, like "thousand".
+    Scale(u64),
+    /// Joins number words, like "and" in "one hundred and five".
+    Connector,
+}
+
+fn number_word(word: &str, lang: &str) -> Option<NumberWord> {
+    use NumberWord::*;
+    let units: &[&str];
+    let tens: &[&str];
+    match lang {
+        "en" => {
+            units = &[
+                "zero",
+                "one",
+                "two",
+                "three",
+                "four",
+                "five",
+                "six",
+                "seven",
+                "eight",
+                "nine",
+                "ten",
+                "eleven",
+                "twelve",
+                "thirteen",
+                "fourteen",
+                "fifteen",
+                "sixteen",
+                "seventeen",
+                "eighteen",
+                "nineteen",
+            ];
+            tens = &[
+                "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty",
+                "ninety",
+            ];
+            match word {
+                "hundred" => return Some(Hundred),
+                "thousand" => return Some(Scale(1_000)),
+                "million" => return Some(Scale(1_000_000)),
+                "billion" => return Some(Scale(1_000_000_000)),
+                "and" => return Some(Connector),
+                _ => (),
+            }
+        }
+        "de" => {
+            units = &[
+                "null",
+                "eins",
+                "zwei",
+                "drei",
+                "vier",
+                "fünf",
+                "sechs",
+                "sieben",
+                "acht",
+                "neun",
+                "zehn",
+                "elf",
+                "zwölf",
+                "dreizehn",
+                "vierzehn",
+                "fünfzehn",
+                "sechzehn",
+                "siebzehn",
+                "achtzehn",
+                "neunzehn",
+            ];
+            tens = &[
+                "", "", "zwanzig", "dreißig", "vierzig", "fünfzig", "sechzig", "siebzig",
+                "achtzig", "neunzig",
+            ];
+            match word {
+                "ein" | "eine" => return Some(Value(1)),
+                "hundert" => return Some(Hundred),
+                "tausend" => return Some(Scale(1_000)),
+                "million" | "millionen" => return Some(Scale(1_000_000)),
+                "milliarde" | "milliarden" => return Some(Scale(1_000_000_000)),
+                _ => (),
+            }
+            // Numbers like "einundzwanzig" and "dreihundert" are one word.
+            for (t, ten) in tens.iter().enumerate().skip(2) {
+                if let Some(unit) = word.strip_suffix(ten).and_then(|w| w.strip_suffix("und")) {
+                    let unit = if unit == "ein" { "eins" } else { unit };
+                    if let Some(u) = units[1..10].iter().position(|x| *x == unit) {
+                        return Some(Value(t as u64 * 10 + u as u64 + 1));
+                    }
+                }
+            }
+            if let Some(unit) = word.strip_suffix("hundert") {
+                let unit = if unit == "ein" { "eins" } else { unit };
+                if let Some(u) = units[1..10].iter().position(|x| *x == unit) {
+                    return Some(Value((u as u64 + 1) * 100));
+                }
+            }
+        }
+        "fr" => {
+            units = &[
+                "zéro", "un", "deux", "trois", "quatre", "cinq", "six", "sept", "huit", "neuf",
+                "dix", "onze", "douze", "treize", "quatorze", "quinze", "seize", "dix-sept",
+                "dix-huit", "dix-neuf",
+            ];
+            tens = &[
+                "",
+                "",
+                "vingt",
+                "trente",
+                "quarante",
+                "cinquante",
+                "soixante",
+                "",
+                "",
+                "",
+            ];
+            match word {
+                "une" => return Some(Value(1)),
+                "vingts" => return Some(Value(20)),
+                "cent" | "cents" => return Some(Hundred),
+                "mille" => return Some(Scale(1_000)),
+                "million" | "millions" => return Some(Scale(1_000_000)),
+                "milliard" | "milliards" => return Some(Scale(1_000_000_000)),
+                "et" => return Some(Connector),
+                _ => (),
+            }
+        }
+        "es" => {
+            units = &[
+                "cero",
+                "uno",
+                "dos",
+                "tres",
+                "cuatro",
+                "cinco",
+                "seis",
+                "siete",
+                "ocho",
+                "nueve",
+                "diez",
+                "once",
+                "doce",
+                "trece",
+                "catorce",
+                "quince",
+                "dieciséis",
+                "diecisiete",
+                "dieciocho",
+                "diecinueve",
+            ];
+            tens = &[
+                "",
+                "",
+                "veinte",
+                "treinta",
+                "cuarenta",
+                "cincuenta",
+                "sesenta",
+                "setenta",
+                "ochenta",
+                "noventa",
+            ];
+            let hundreds = [
+                "",
+                "ciento",
+                "doscientos",
+                "trescientos",
+                "cuatrocientos",
+                "quinientos",
+                "seiscientos",
+                "setecientos",
+                "ochocientos",
+                "novecientos",
+            ];
+            let twenties = [
+                "veinte",
+                "veintiuno",
+                "veintidós",
+                "veintitrés",
+                "veinticuatro",
+                "veinticinco",
+                "veintiséis",
+                "veintisiete",
+                "veintiocho",
+                "veintinueve",
+            ];
+            match word {
+                "un" | "una" => return Some(Value(1)),
+                "cien" => return Some(Value(100)),
+                "mil" => return Some(Scale(1_000)),
+                "millón" | "millones" => return Some(Scale(1_000_000)),
+                "y" => return Some(Connector),
+                _ => (),
+            }
+            if let Some(h) = hundreds.iter().skip(1).position(|x| *x == word) {
+                return Some(Value((h as u64 + 1) * 100));
+            }
+            if let Some(t) = twenties.iter().position(|x| *x == word) {
+                return Some(Value(20 + t as u64));
+            }
+        }
+        _ => return None,
+    }
+    if let Some(u) = units.iter().position(|x| *x == word) {
+        return Some(Value(u as u64));
+    }
+    tens.iter()
+        .position(|x| !x.is_empty() && *x == word)
+        .map(|t| Value(t as u64 * 10))
+}
+
+/// Words which are "one" but mostly indefinite articles, and so only a
+/// number as part of a longer one, like "eine Million".
+fn is_article(word: &str, lang: &str) -> bool {
+    let word = word.to_lowercase();
+    match lang {
+        "de" => word == "ein" || word == "eine",
+        "fr" => word == "un" || word == "une",
+        "es" => word == "un" || word == "una",
+        _ => false,
+    }
+}
+
+/// Adds up a run of number words.
+struct NumberParser {
+    total: u64,
+    current: u64,
+    last: Option<NumberWord>,
+    words: usize,
+    /// The first word of the run, as written.
+    first: String,
+}
+
+impl NumberParser {
+    fn new() -> Self {
+        Self {
+            total: 0,
+            current: 0,
+            last: None,
+            words: 0,
+            first: String::new(),
+        }
+    }
+
+    /// Whether `word` continues the number so far, rather than starting
+    /// another, as "five" does after "twenty" but not after "three".
+    fn accepts(&self, word: NumberWord, lang: &str) -> bool {
+        use NumberWord::*;
+        match (self.last, word) {
+            (None, _) | (_, Connector) => true,
+            (Some(Hundred), Value(v)) => v < 100,
+            (Some(Scale(s)), Value(v)) => v < s,
+            // French says eighty as "quatre-vingts", four twenties.
+            (Some(Value(4)), Value(20)) => lang == "fr",
+            (Some(Value(l)), Value(v)) if l >= 100 && l % 100 == 0 => v < 100,
+            // French counts on from sixty and eighty in tens, "soixante-dix".
+            (Some(Value(l)), Value(v)) if l >= 20 && l % 10 == 0 => {
+                v < if lang == "fr" { 20 } else { 10 }
+            }
+            (Some(Value(_)), Value(_)) => false,
+            (Some(Value(l)), Hundred) => l < 100,
+            (Some(Value(_)) | Some(Hundred), Scale(_)) => true,
+            _ => false,
+        }
+    }
+
+    fn push(&mut self, word: NumberWord, lang: &str) {
+        match word {
+            NumberWord::Value(v) => {
+                if lang == "fr" && v == 20 && matches!(self.last, Some(NumberWord::Value(4))) {
+                    self.current += 76;
+                } else {
+                    self.current += v;
+                }
+            }
+            NumberWord::Hundred => self.current = self.current.max(1) * 100,
+            NumberWord::Scale(scale) => {
+                self.total += self.current.max(1) * scale;
+                self.current = 0;
+            }
+            NumberWord::Connector => return,
+        }
+        self.last = Some(word);
+        self.words += 1;
+    }
+
+    fn value(&self) -> Option<u64> {
+        if self.words == 0 {
+            return None;
+        }
+        Some(self.total + self.current)
+    }
+
+    /// The run as it is written out: its value, or the word itself if it
+    /// is an article on its own.
+    fn text(&self, lang: &str) -> Option<String> {
+        if self.words == 1 && is_article(&self.first, lang) {
+            return Some(self.first.clone());
+        }
+        self.value().map(|v| v.to_string())
+    }
+}
+
+/// The number words making up `token`, which may be joined with hyphens
+/// like "twenty-one".
+fn number_words(token: &str, lang: &str) -> Option<Vec<NumberWord>> {
+    let token = token.to_lowercase();
+    if let Some(w) = number_word(&token, lang) {
+        return Some(vec![w]);
+    }
+    if !token.contains('-') {
+        return None;
+    }
+    token
+        .split('-')
+        .map(|part| number_word(part, lang))
+        .collect()
+}
+
+/// Removes thousands separators from numbers written in digits, like
+/// "1,000" in English or "1.000" in German.
+fn strip_thousands_separators(token: &str, lang: &str) -> String {
+    let separator = if lang == "en" { ',' } else { '.' };
+    let groups: Vec<&str> = token.split(separator).collect();
+    let is_grouped = groups.len() > 1
+        && (1..=3).contains(&groups[0].len())
+        && groups.iter().all(|g| g.chars().all(|c| c.is_ascii_digit()))
+        && groups[1..].iter().all(|g| g.len() == 3);
+    if is_grouped {
+        groups.concat()
+    } else {
+        token.to_string()
+    }
+}
+
+fn normalize_numbers(text: &str, lang: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut parser = NumberParser::new();
+    // Whitespace and connectors seen since the last number word, kept in
+    // case the run of number words has ended.
+    let mut pending = String::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let space_len = rest.len() - rest.trim_start().len();
+        pending.push_str(&rest[..space_len]);
+        rest = &rest[space_len..];
+        if rest.is_empty() {
+            break;
+        }
+        let token_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let token = &rest[..token_len];
+        rest = &rest[token_len..];
+
+        let word = token.trim_end_matches(|c: char| !c.is_alphanumeric());
+        let trailing = &token[word.len()..];
+        match number_words(word, lang) {
+            Some(words)
+                if !(parser.words == 0
+                    && words.iter().all(|w| matches!(w, NumberWord::Connector))) =>
+            {
+                let connector = words.iter().all(|w| matches!(w, NumberWord::Connector));
+                if !parser.accepts(words[0], lang) {
+                    // This starts another number.
+                    if let Some(text) = parser.text(lang) {
+                        result.push_str(&text);
+                    }
+                    parser = NumberParser::new();
+                }
+                if parser.words == 0 {
+                    result.push_str(&pending);
+                    pending.clear();
+                    parser.first = word.to_string();
+                }
+                for w in words {
+                    parser.push(w, lang);
+                }
+                if connector {
+                    pending.push_str(token);
+                    continue;
+                }
+                if !trailing.is_empty() {
+                    // Punctuation ends the number.
+                    if let Some(text) = parser.text(lang) {
+                        result.push_str(&text);
+                    }
+                    result.push_str(trailing);
+                    parser = NumberParser::new();
+                }
+                pending.clear();
+            }
+            _ => {
+                if let Some(text) = parser.text(lang) {
+                    result.push_str(&text);
+                    parser = NumberParser::new();
+                }
+                result.push_str(&pending);
+                pending.clear();
+                result.push_str(&strip_thousands_separators(word, lang));
+                result.push_str(trailing);
+            }
+        }
+    }
+    if let Some(text) = parser.text(lang) {
+        result.push_str(&text);
+    }
+    result.push_str(&pending);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(text: &str, lang: &str) -> String {
+        normalize_numbers(text, lang)
+    }
+
+    #[test]
+    fn articles_are_not_numbers() {
+        assert_eq!(numbers("ein Haus", "de"), "ein Haus");
+        assert_eq!(numbers("Eine Frage, bitte.", "de"), "Eine Frage, bitte.");
+        assert_eq!(numbers("une maison", "fr"), "une maison");
+        assert_eq!(numbers("un perro y una casa", "es"), "un perro y una casa");
+    }
+
+    #[test]
+    fn articles_start_longer_numbers() {
+        assert_eq!(numbers("eine Million Menschen", "de"), "1000000 Menschen");
+        assert_eq!(numbers("ein hundert Jahre", "de"), "100 Jahre");
+        assert_eq!(numbers("un millón", "es"), "1000000");
+    }
+
+    #[test]
+    fn german_compound_numbers() {
+        assert_eq!(numbers("einhundert", "de"), "100");
+        assert_eq!(numbers("einundzwanzig Tage", "de"), "21 Tage");
+        assert_eq!(numbers("dreihundert", "de"), "300");
+        assert_eq!(numbers("eins", "de"), "1");
+    }
+
+    #[test]
+    fn english_numbers() {
+        assert_eq!(numbers("one hundred and five people", "en"), "105 people");
+        assert_eq!(numbers("twenty-one", "en"), "21");
+        assert_eq!(numbers("three four", "en"), "3 4");
+        assert_eq!(numbers("and so on", "en"), "and so on");
+        assert_eq!(numbers("1,000 years", "en"), "1000 years");
+    }
+
+    #[test]
+    fn french_numbers() {
+        assert_eq!(numbers("quatre-vingts", "fr"), "80");
+        assert_eq!(numbers("soixante-dix", "fr"), "70");
+        assert_eq!(numbers("vingt et un", "fr"), "21");
+    }
+
+    #[test]
+    fn punctuation_ends_numbers() {
+        assert_eq!(numbers("five, six", "en"), "5, 6");
+    }
+
+    #[test]
+    fn scales() {
+        assert_eq!(
+            numbers("two thousand three hundred and forty-five", "en"),
+            "2345"
+        );
+        assert_eq!(numbers("three million people", "en"), "3000000 people");
+        assert_eq!(numbers("zwei Millionen", "de"), "2000000");
+        assert_eq!(numbers("mil quinientos", "es"), "1500");
+    }
+
+    #[test]
+    fn connectors_after_numbers_are_kept() {
+        assert_eq!(numbers("five and", "en"), "5 and");
+        assert_eq!(numbers("five and then six", "en"), "5 and then 6");
+        assert_eq!(numbers("  seven  ", "en"), "  7  ");
+    }
+
+    #[test]
+    fn thousands_separators() {
+        assert_eq!(numbers("1.000.000 Euro", "de"), "1000000 Euro");
+        assert_eq!(numbers("1,000,000 dollars", "en"), "1000000 dollars");
+        // decimals, and numbers grouped wrongly, are left alone.
+        assert_eq!(numbers("1,5 Prozent", "de"), "1,5 Prozent");
+        assert_eq!(numbers("3.14", "en"), "3.14");
+        assert_eq!(numbers("1,00", "en"), "1,00");
+    }
+
+    #[test]
+    fn unknown_languages_are_left_alone() {
+        assert_eq!(numbers("uno dos", "it"), "uno dos");
+    }
+
+    #[test]
+    fn punctuation() {
+        assert_eq!(
+            strip_punctuation("Hello, world! «Ja» – 3.5 und 1,000."),
+            "Hello world Ja  3.5 und 1,000"
+        );
+    }
+
+    #[test]
+    fn options() {
+        let text = "Zwanzig  Gäste\n\nkamen.";
+        let only = |n: Normalization| n.apply(text, "de");
+        assert_eq!(only(Normalization::default()), text);
+        assert_eq!(
+            only(Normalization {
+                lowercase: true,
+                ..Default::default()
+            }),
+            "zwanzig  gäste\n\nkamen."
+        );
+        assert_eq!(
+            only(Normalization {
+                punctuation: true,
+                ..Default::default()
+            }),
+            "Zwanzig  Gäste\n\nkamen"
+        );
+        assert_eq!(
+            only(Normalization {
+                whitespace: true,
+                ..Default::default()
+            }),
+            "Zwanzig Gäste kamen."
+        );
+        assert_eq!(
+            only(Normalization {
+                numbers: true,
+                ..Default::default()
+            }),
+            "20  Gäste\n\nkamen."
+        );
+        assert_eq!(
+            only(Normalization {
+                all: true,
+                ..Default::default()
+            }),
+            "20 gäste kamen"
+        );
+    }
+
+    #[test]
+    fn composed_and_decomposed_accents() {
+        let nfc = Normalization {
+            nfc: true,
+            ..Default::default()
+        };
+        assert_eq!(nfc.apply("Ga\u{308}ste", "de"), "G\u{e4}ste");
+        assert_eq!(nfc.apply("G\u{e4}ste", "de"), "G\u{e4}ste");
+    }
+
+    #[test]
+    fn empty_normalizations() {
+        assert!(Normalization::default().is_empty());
+        assert!(!Normalization {
+            all: true,
+            ..Default::default()
+        }
+        .is_empty());
+        assert!(!Normalization {
+            whitespace: true,
+            ..Default::default()
+        }
+        .is_empty());
+    }
+}
@@ -0,0 +1,79 @@
+use chrono::{Duration, Utc};
+
+use crate::db;
+use crate::error::{Er, E};
+use crate::session::{delete_session, expire_sessions};
+
+/// How long sessions are kept around. Configured with these environment
+/// variables:
+/// - `SESSION_MEMORY_HOURS` how long a session stays in memory after its last
+///   update, default 24.
+/// - `SESSION_RETENTION_DAYS` how long a session is kept in the database and
+///   on disk after its last update. By default sessions are kept forever.
+///   Starred sessions are always kept.
+/// - `SESSION_ARCHIVE_DIR` if set, the recording directories of expired
+///   sessions are moved here instead of being deleted.
+/// - `RETENTION_INTERVAL_MINUTES` how often the policy is applied, default 60.
+#[derive(Clone, Debug)]
+pub struct RetentionPolicy {
+    pub memory: Duration,
+    pub disk: Option<Duration>,
+    pub archive_dir: Option<String>,
+    pub interval: std::time::Duration,
+}
+
+impl RetentionPolicy {
+    pub fn from_env() -> E<Self> {
+        let memory_hours: i64 = match std::env::var("SESSION_MEMORY_HOURS") {
+            Ok(hours) if !hours.trim().is_empty() => hours.trim().parse()?,
+            _ => 24,
+        };
+        let disk = match std::env::var("SESSION_RETENTION_DAYS") {
+            Ok(days) if !days.trim().is_empty() => Some(Duration::days(days.trim().parse()?)),
+            _ => None,
+        };
+        let interval_minutes: u64 = match std::env::var("RETENTION_INTERVAL_MINUTES") {
+            Ok(minutes) if !minutes.trim().is_empty() => minutes.trim().parse()?,
+            _ => 60,
+        };
+        if interval_minutes == 0 {
+            return Err(Er::new(
+                "RETENTION_INTERVAL_MINUTES must be at least 1".to_string(),
+            ));
+        }
+        Ok(Self {
+            memory: Duration::hours(memory_hours),
+            disk,
+            archive_dir: std::env::var("SESSION_ARCHIVE_DIR")
+                .ok()
+                .filter(|dir| !dir.is_empty()),
+            interval: std::time::Duration::from_secs(interval_minutes * 60),
+        })
+    }
+
+    pub async fn apply(&self) -> E<()> {
+        expire_sessions(self.memory).await?;
+        if let Some(disk) = self.disk {
+            let expired = db::expired_sessions(Utc::now() - disk)?;
+            for session_id in expired {
+                log::info!("Removing expired session {}", session_id);
+                if let Err(e) = delete_session(session_id, self.archive_dir.as_deref()).await {
+                    log::error!("Couldn't remove expired session {}: {:?}", session_id, e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Applies the retention policy periodically, forever.
+pub async fn run(policy: RetentionPolicy) {
+    log::debug!("Starting retention task with policy {:?}", policy);
+    let mut interval = tokio::time::interval(policy.interval);
+    loop {
+        interval.tick().await;
+        if let Err(e) = policy.apply().await {
+            log::error!("Error applying retention policy: {:?}", e);
+        }
+    }
+}
@@ -19,7 +19,10 @@ use warp::ws::{Message, WebSocket};
 
 const RECV_TIMEOUT_SECONDS: u64 = 15;
 
-use crate::error::E;
+use crate::auth::User;
+use crate::db;
+use crate::error::{ApiError, Er, E};
+use crate::events::{self, SessionEvent};
 use crate::queue::{self};
 use crate::translate::{self, TranslationResponse, TranslationResponses};
 
@@ -44,21 +47,55 @@ pub struct SessionData {
     pub buffer: Vec<f32>,
     #[serde(skip_serializing)]
     pub silence_length: usize,
+    /// How many samples have been sent for transcription so far.
+    #[serde(skip_serializing)]
+    pub sent_samples: usize,
+    /// The last sequence in the comparison last sent to the interpreter.
+    #[serde(skip_serializing)]
+    pub compared_sequence: Option<usize>,
     pub sequence_number: usize,
     #[serde(skip_serializing)]
     pub last_sequence: Option<usize>,
     #[serde(skip_serializing)]
-    pub recording: bool,
-    #[serde(skip_serializing)]
     pub recording_file: Option<String>,
     #[serde(skip_serializing)]
     pub transcript_file: Option<String>,
     #[serde(skip_serializing)]
+    pub segments_file: Option<String>,
+    #[serde(skip_serializing)]
     pub translations: Arc<Mutex<TranslationResponses>>,
     pub updated_at: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
+    pub starred: bool,
+    pub state: SessionState,
+    #[serde(skip_serializing)]
+    pub user_id: Option<usize>,
+    #[serde(skip_serializing)]
+    pub assignment_id: Option<usize>,
+}
+
+/// Where a session is in its lifecycle: receiving audio, transcribing the
+/// audio it has received, or finished.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionState {
+    Live,
+    Transcribing,
+    Finished,
 }
 
+impl SessionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionState::Live => "live",
+            SessionState::Transcribing => "transcribing",
+            SessionState::Finished => "finished",
+        }
+    }
+}
+
+/// The contents of `metadata.json` in a session's recording directory. Only
+/// used to import sessions recorded before they were kept in the database.
 #[derive(Deserialize)]
 struct SavedSessionData {
     pub language: String,
@@ -68,6 +105,8 @@ struct SavedSessionData {
     pub updated_at: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
     pub transcript: Option<String>,
+    #[serde(default)]
+    pub starred: bool,
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -78,6 +117,7 @@ pub struct Status {
     pub sample_rate: u32,
     pub transcription_job_count: usize,
     pub transcription_completed_count: usize,
+    pub state: SessionState,
 }
 
 impl SessionData {
@@ -88,6 +128,7 @@ impl SessionData {
         sample_rate: u32,
         resource: Option<String>,
         _uuid: Option<Uuid>,
+        user_id: Option<usize>,
     ) -> Self {
         let uuid = if let Some(u) = _uuid {
             u
@@ -96,11 +137,13 @@ impl SessionData {
         };
         let mut recording_file = None;
         let mut transcript_file = None;
+        let mut segments_file = None;
         if let Ok(dir) = std::env::var("RECORDINGS_DIR") {
             let new_dir = format!("{}/{}", dir, uuid);
             if std::fs::create_dir_all(new_dir.clone()).is_ok() {
                 recording_file = Some(format!("{}/{}.wav", new_dir, uuid));
                 transcript_file = Some(format!("{}/{}.txt", new_dir, uuid));
+                segments_file = Some(format!("{}/{}.json", new_dir, uuid));
             }
         };
         Self {
@@ -109,11 +152,13 @@ impl SessionData {
             language,
             sample_rate,
             silence_length: 0usize,
+            sent_samples: 0,
+            compared_sequence: None,
             uuid,
             resource,
-            recording: recording_file.is_some(),
             recording_file,
             transcript_file,
+            segments_file,
             valid: true,
             buffer: Vec::new(),
             sequence_number: 0,
@@ -121,9 +166,31 @@ impl SessionData {
             translations: Arc::new(Mutex::new(TranslationResponses::new())),
             updated_at: Utc::now(),
             created_at: Utc::now(),
+            starred: false,
+            state: SessionState::Live,
+            user_id,
+            assignment_id: None,
+        }
+    }
+
+    /// Sessions belong to the user who recorded them. Sessions recorded
+    /// without logging in are visible to anyone who isn't logged in. Admins
+    /// see everything.
+    pub fn is_visible_to(&self, user: &Option<User>) -> bool {
+        match user {
+            Some(u) if u.is_admin() => true,
+            _ => self.user_id == user.as_ref().map(|u| u.id),
         }
     }
 
+    /// The directory holding this session's recording, transcript and
+    /// metadata, if recordings are kept.
+    pub fn recording_dir(&self) -> Option<String> {
+        std::env::var("RECORDINGS_DIR")
+            .ok()
+            .map(|dir| format!("{}/{}", dir, self.uuid))
+    }
+
     pub fn get_translation_count(&self) -> E<usize> {
         let mutex = self.translations.lock().unwrap();
         let responses: &crate::translate::TranslationResponses = mutex.deref();
@@ -148,21 +215,33 @@ impl SessionData {
         Ok(responses.to_string())
     }
 
-    pub fn finalize_session(&mut self) {
-        self.record_transcript()
-            .expect("error recording transcript");
-        self.write_metadata().expect("error writing metadata");
+    /// Marks the session finished everywhere. It is finished even if its
+    /// files can't be written, which is the error returned.
+    pub fn finalize_session(&mut self) -> E<()> {
+        self.state = SessionState::Finished;
+        let written = self
+            .record_transcript()
+            .and_then(|_| self.record_segments())
+            .and_then(|_| self.write_metadata());
+        if let Err(e) = db::finalize_session(self) {
+            log::error!("Couldn't finalize session {} in database: {:?}", self.id, e);
+        }
+        crate::webhook::notify(self);
         mutate_session_sync(&self.id, |session| {
             let sender = session.transcription_sender_tx.take();
             drop(sender);
             session.valid = false;
+            session.state = SessionState::Finished;
             log::debug!("good bye user: {}", session.id);
         });
+        events::publish_status(self);
+        events::close(self.id);
+        written
     }
 
     fn write_metadata(&self) -> E<()> {
-        if let Ok(dir) = std::env::var("RECORDINGS_DIR") {
-            let metadata_file = format!("{}/{}/metadata.json", dir, self.uuid);
+        if let Some(dir) = self.recording_dir() {
+            let metadata_file = format!("{}/metadata.json", dir);
             let mut file = std::fs::File::create(metadata_file)?;
             let json = json!(self).to_string();
             file.write_all(json.as_bytes())?;
@@ -180,6 +259,17 @@ impl SessionData {
         Ok(())
     }
 
+    /// Writes the transcript with its segment boundaries and timings, so that
+    /// it can be restored exactly.
+    fn record_segments(&self) -> E<()> {
+        if let Some(filename) = &self.segments_file {
+            let file = std::fs::File::create(filename)?;
+            let mutex = self.translations.lock().unwrap();
+            serde_json::to_writer(file, mutex.deref())?;
+        }
+        Ok(())
+    }
+
     pub fn status(&self) -> E<Status> {
         Ok(Status {
             language: self.language.clone(),
@@ -188,6 +278,7 @@ impl SessionData {
             sample_rate: self.sample_rate,
             transcription_job_count: self.sequence_number,
             transcription_completed_count: self.get_translation_count()?,
+            state: self.state,
         })
     }
 }
@@ -205,30 +296,51 @@ lazy_static! {
         .thread_stack_size(3 * 1024 * 1024)
         .build()
         .unwrap();
+    /// Sessions which have been started since the server came up. Everything
+    /// else is loaded from the database on demand.
     pub static ref SESSIONS: RwLock<Sessions> = RwLock::new(Sessions::default());
 }
 
 pub fn process_transcription(session_id: usize, response: &TranslationResponse) -> E<()> {
-    let mut session = get_session_sync(&session_id).unwrap();
+    let mut session = match get_session_sync(&session_id) {
+        Some(session) => session,
+        None => {
+            log::warn!(
+                "Dropping segment of session {}, which no longer exists",
+                session_id
+            );
+            return Ok(());
+        }
+    };
     log::debug!(
         "Sending {:?} to user\nSessionData is {}, last_sequence = {:?}",
         response,
-        json!(session).to_string(),
+        json!(session),
         session.last_sequence,
     );
-    match session.transcription_sender_tx.as_ref() {
-        Some(sender) => match sender.send(Message::text(json!(response).to_string())) {
-            Ok(_) => (),
-            Err(e) => log::error!("Couldn't send {:?}", e),
-        },
-        None => log::warn!("No sender for session {}", session_id),
-    };
+    if session.transcription_sender_tx.is_none() {
+        log::warn!("No sender for session {}", session_id);
+    }
+    if let Err(e) = events::send(&session, SessionEvent::Segment(response.clone())) {
+        log::error!("Couldn't send {:?}", e);
+    }
     session
         .translations
         .lock()
         .unwrap()
         .deref_mut()
         .add_translation(&response.clone())?;
+    if let Err(e) = db::insert_segment(session_id, response) {
+        log::error!(
+            "Couldn't store segment of session {} in database: {:?}",
+            session_id,
+            e
+        );
+    }
+    events::publish_status(&session);
+    if let Some(resource) = &session.resource {
+        send_comparison(&session, resource);
+    }
 
     if let Some(last) = session.last_sequence {
         if session.sequence_number >= last && response.segment_number == response.num_segments - 1 {
@@ -239,7 +351,13 @@ pub fn process_transcription(session_id: usize, response: &TranslationResponse)
                             "Last sequence set and reached. Finalizing session {}.",
                             session_id
                         );
-                        session.finalize_session();
+                        if let Err(e) = session.finalize_session() {
+                            log::error!(
+                                "Couldn't write the files of session {}: {:?}",
+                                session_id,
+                                e
+                            );
+                        }
                     }
                 }
             }
@@ -248,12 +366,44 @@ pub fn process_transcription(session_id: usize, response: &TranslationResponse)
     Ok(())
 }
 
+/// Sends the interpreter the comparison with the reference as it stands,
+/// with the changes since the one sent before.
+fn send_comparison(session: &SessionData, resource: &String) {
+    if session.transcription_sender_tx.is_none() {
+        return;
+    }
+    let comparison = match crate::compare::live(session, resource, session.compared_sequence) {
+        Ok(c) => c,
+        Err(e) => {
+            log::debug!("Couldn't compare session {}: {:?}", session.id, e);
+            return;
+        }
+    };
+    let sequence = comparison.changes.sequence;
+    match events::send(session, SessionEvent::Comparison(comparison)) {
+        Ok(_) => mutate_session_sync(&session.id, |session| {
+            session.compared_sequence = Some(sequence)
+        }),
+        Err(e) => log::error!("Couldn't send comparison {:?}", e),
+    }
+}
+
 pub async fn get_session(id: &usize) -> Option<SessionData> {
-    SESSIONS.read().await.get(id).cloned()
+    if let Some(session) = SESSIONS.read().await.get(id).cloned() {
+        return Some(session);
+    }
+    load_session(id)
 }
 
-pub async fn get_sessions() -> Option<Vec<SessionData>> {
-    Some(SESSIONS.read().await.iter().map(|x| x.1.clone()).collect())
+/// The sessions belonging to the given user, or the anonymous sessions.
+pub async fn get_sessions(user_id: Option<usize>) -> Option<Vec<SessionData>> {
+    match db::load_sessions(user_id) {
+        Ok(sessions) => Some(sessions),
+        Err(e) => {
+            log::error!("Couldn't load sessions: {:?}", e);
+            None
+        }
+    }
 }
 
 pub fn get_session_sync(id: &usize) -> Option<SessionData> {
@@ -261,7 +411,17 @@ pub fn get_session_sync(id: &usize) -> Option<SessionData> {
     SYNC_BRIDGE_RUNTIME.block_on(async {
         session = SESSIONS.read().await.get(id).cloned();
     });
-    session
+    session.or_else(|| load_session(id))
+}
+
+fn load_session(id: &usize) -> Option<SessionData> {
+    match db::load_session(id) {
+        Ok(session) => session,
+        Err(e) => {
+            log::error!("Couldn't load session {}: {:?}", id, e);
+            None
+        }
+    }
 }
 
 async fn set_session(id: usize, session: SessionData) {
@@ -270,12 +430,13 @@ async fn set_session(id: usize, session: SessionData) {
 
 // returns the id of the session with given uuid.
 pub async fn find_session_with_uuid(uuid: &String) -> Option<usize> {
-    for element in SESSIONS.read().await.iter() {
-        if element.1.uuid.to_string().eq(uuid) {
-            return Some(*element.0);
+    match db::find_session_id(uuid) {
+        Ok(id) => id,
+        Err(e) => {
+            log::error!("Couldn't look up session {}: {:?}", uuid, e);
+            None
         }
     }
-    None
 }
 
 pub async fn mutate_session<F>(id: &usize, mut f: F)
@@ -300,6 +461,17 @@ where
 async fn remove_session(id: &usize) {
     let mut sessions = SESSIONS.write().await;
     sessions.remove(id);
+    events::forget(*id);
+}
+
+/// Whether the session is being recorded or transcribed here, so that more
+/// is to come from it. Sessions left unfinished by a restart aren't.
+pub async fn is_active(id: &usize) -> bool {
+    SESSIONS
+        .read()
+        .await
+        .get(id)
+        .is_some_and(|session| session.state != SessionState::Finished)
 }
 
 pub async fn user_message(session_id: usize, msg: Message) -> E<()> {
@@ -339,6 +511,7 @@ pub async fn user_message(session_id: usize, msg: Message) -> E<()> {
                 let payload = session.buffer[..pivot].to_vec();
                 let lang = session.language.clone();
                 persist_session_data(&session, pivot)?;
+                record_sequence_start(&session, sequence_number);
                 let result = queue::get_queue().enqueue(translate::TranslationRequest {
                     session_id,
                     sequence_number,
@@ -353,8 +526,12 @@ pub async fn user_message(session_id: usize, msg: Message) -> E<()> {
                             session.silence_length = silence_length;
                             session.buffer = session.buffer[pivot..].to_vec();
                             session.sequence_number += 1;
+                            session.sent_samples += pivot;
                         })
                         .await;
+                        if let Some(session) = get_session(&session_id).await {
+                            events::publish_status(&session);
+                        }
                     }
                     Err(_) => {
                         drop(result);
@@ -363,6 +540,10 @@ pub async fn user_message(session_id: usize, msg: Message) -> E<()> {
                             session.valid = false;
                         })
                         .await;
+                        events::publish_error(
+                            session_id,
+                            "Couldn't send audio for transcription".to_string(),
+                        );
                     }
                 }
             }
@@ -376,6 +557,8 @@ pub async fn user_connected(
     lang: String,
     sample_rate: u32,
     resource: Option<String>,
+    user_id: Option<usize>,
+    assignment_id: Option<usize>,
 ) {
     let session_id = NEXT_USER_ID.fetch_add(1, Ordering::Relaxed);
 
@@ -397,6 +580,7 @@ pub async fn user_connected(
         }
         log::debug!("Exiting loop");
         if let Some(session) = get_session(&session_id).await {
+            record_sequence_start(&session, session.sequence_number);
             match queue::get_queue().enqueue(translate::TranslationRequest {
                 session_id,
                 sequence_number: session.sequence_number,
@@ -406,10 +590,14 @@ pub async fn user_connected(
                 Ok(_) => log::debug!("Flushed session data"),
                 Err(e) => log::error!("Error flushing session buffer: {:?}", e),
             }
-            mutate_session(&session_id, |session| session.sequence_number += 1).await;
+            mutate_session(&session_id, |session| {
+                session.sequence_number += 1;
+                session.sent_samples += session.buffer.len();
+            })
+            .await;
             match persist_session_data(&session, session.buffer.len()) {
                 Ok(_) => (),
-                Err(e) => log::error!("Error in final session data persist"),
+                Err(e) => log::error!("Error in final session data persist: {:?}", e),
             }
         }
 
@@ -423,8 +611,13 @@ pub async fn user_connected(
         sample_rate,
         resource,
         None,
+        user_id,
     );
+    session.assignment_id = assignment_id;
     session.send_uuid().unwrap();
+    if let Err(e) = db::insert_session(&session) {
+        log::error!("Couldn't save session {}: {:?}", session_id, e);
+    }
     set_session(session_id, session).await;
 
     while let Ok(Some(result)) =
@@ -477,8 +670,16 @@ pub async fn mark_session_for_closure(session_id: usize) {
         // session was never used.
         mutate_session(&session_id, |session| {
             session.transcription_sender_tx = None;
+            session.state = SessionState::Finished;
         })
         .await;
+        if let Err(e) = db::set_state(&session_id, SessionState::Finished) {
+            log::error!("Couldn't update state of session {}: {:?}", session_id, e);
+        }
+        if let Some(session) = get_session(&session_id).await {
+            events::publish_status(&session);
+        }
+        events::close(session_id);
         return;
     }
     let payload = session.buffer.to_vec();
@@ -487,41 +688,120 @@ pub async fn mark_session_for_closure(session_id: usize) {
         Ok(_) => (),
         Err(e) => log::error!("Couldn't persist session data: {:?}", e),
     };
-    log::debug!(
-        "Sending last {} samples to translate for session {}",
-        session.buffer.len(),
-        session_id
-    );
-    match queue::get_queue().enqueue(translate::TranslationRequest {
-        session_id,
-        sequence_number: session.sequence_number,
-        payload,
-        lang,
-    }) {
-        Ok(_) => (),
-        Err(e) => log::error!("Error enqueuing final audio: {:?}", e),
-    };
+    record_sequence_start(&session, session.sequence_number);
     let last_sequence = session.sequence_number;
     log::debug!(
         "Found session {}, marking it for closure at sequence number {}",
         session_id,
         last_sequence,
     );
+    // before the last audio is queued, since once it is transcribed the
+    // session is finished, which mustn't be undone.
     mutate_session(&session_id, |session| {
+        session.sent_samples += session.buffer.len();
         session.buffer = vec![];
         session.last_sequence = Some(last_sequence);
         session.sequence_number = last_sequence + 1;
+        session.state = SessionState::Transcribing;
     })
     .await;
+    if let Err(e) = db::set_state(&session_id, SessionState::Transcribing) {
+        log::error!("Couldn't update state of session {}: {:?}", session_id, e);
+    }
+    log::debug!(
+        "Sending last {} samples to translate for session {}",
+        payload.len(),
+        session_id
+    );
+    match queue::get_queue().enqueue(translate::TranslationRequest {
+        session_id,
+        sequence_number: last_sequence,
+        payload,
+        lang,
+    }) {
+        Ok(_) => (),
+        Err(e) => {
+            log::error!("Error enqueuing final audio: {:?}", e);
+            events::publish_error(
+                session_id,
+                "Couldn't send audio for transcription".to_string(),
+            );
+        }
+    };
+    if let Some(session) = get_session(&session_id).await {
+        events::publish_status(&session);
+    }
+}
+
+/// Drops finished sessions which haven't been updated for `max_age` from
+/// memory. They are still in the database, and are loaded from there when
+/// needed. Unfinished sessions are kept, since their recording and
+/// transcription need them.
+pub async fn expire_sessions(max_age: chrono::Duration) -> E<()> {
+    let now = Utc::now();
+    let expired: Vec<usize> = SESSIONS
+        .read()
+        .await
+        .iter()
+        .filter(|(_, session)| {
+            session.state == SessionState::Finished && now - session.updated_at > max_age
+        })
+        .map(|(session_id, _)| *session_id)
+        .collect();
+    for session_id in expired {
+        log::debug!("Expiring session {} from memory", session_id);
+        remove_session(&session_id).await;
+    }
+    Ok(())
 }
 
-pub async fn expire_sessions() -> E<()> {
-    let now = Utc::now().timestamp();
-    for (session_id, session) in (*SESSIONS).read().await.iter() {
-        if now - session.updated_at.timestamp() > 86400 {
-            remove_session(session_id).await;
+/// Removes a session from memory and the database. Its recording directory
+/// is moved to `archive_dir` if given, otherwise it is deleted. Sessions
+/// still being recorded or transcribed can't be removed.
+pub async fn delete_session(session_id: usize, archive_dir: Option<&str>) -> E<()> {
+    let session = match get_session(&session_id).await {
+        Some(s) => s,
+        None => return Err(Er::new(format!("Session {} not found", session_id))),
+    };
+    {
+        // its recording and transcription jobs would otherwise go on writing
+        // to it once it is gone. Sessions left unfinished by a restart have
+        // none, and aren't in memory.
+        let mut sessions = SESSIONS.write().await;
+        if let Some(active) = sessions.get(&session_id) {
+            if active.state != SessionState::Finished {
+                return Err(Box::new(ApiError::Conflict(format!(
+                    "Session {} is still being recorded or transcribed",
+                    session.uuid
+                ))));
+            }
+        }
+        sessions.remove(&session_id);
+        events::forget(session_id);
+    }
+    crate::compare::forget_changes(session_id);
+    if let Some(dir) = session.recording_dir() {
+        if std::path::Path::new(&dir).exists() {
+            match archive_dir {
+                Some(archive_dir) => {
+                    std::fs::create_dir_all(archive_dir)?;
+                    std::fs::rename(&dir, format!("{}/{}", archive_dir, session.uuid))?;
+                }
+                None => std::fs::remove_dir_all(&dir)?,
+            }
         }
     }
+    db::delete_session(&session_id)?;
+    Ok(())
+}
+
+pub async fn star_session(uuid: String, starred: bool) -> E<()> {
+    let session_id = match find_session_with_uuid(&uuid).await {
+        Some(id) => id,
+        None => return Err(Er::new(format!("Session {} not found", uuid))),
+    };
+    db::set_starred(&session_id, starred)?;
+    mutate_session(&session_id, |session| session.starred = starred).await;
     Ok(())
 }
 
@@ -547,71 +827,123 @@ fn persist_session_data(session: &SessionData, length: usize) -> E<()> {
     Ok(())
 }
 
+/// Saves where the audio about to be sent as `sequence_number` starts in the
+/// recording, so that segment times can be placed on the session's timeline.
+fn record_sequence_start(session: &SessionData, sequence_number: usize) {
+    let start_ms = session.sent_samples as i64 * 1000 / session.sample_rate.max(1) as i64;
+    if let Err(e) = db::insert_sequence_start(&session.id, sequence_number, start_ms) {
+        log::error!(
+            "Couldn't save start of sequence {} of session {}: {:?}",
+            sequence_number,
+            session.id,
+            e
+        );
+    }
+}
+
+/// Sessions live in the database, so all that needs restoring is the id
+/// counter. Recordings made before the database existed are imported from
+/// their `metadata.json` files the first time the server sees them. Those
+/// which can't be imported are logged and skipped.
 pub async fn restore_sessions() -> E<()> {
-    let mut saved_sessions: Vec<SavedSessionData> = vec![];
+    let mut next_id = db::max_session_id()? + 1;
     if let Ok(dir) = std::env::var("RECORDINGS_DIR") {
         for entry in std::fs::read_dir(dir.clone())? {
-            let entry = entry?;
-            if entry.metadata()?.is_dir() {
-                if let Ok(contents) = std::fs::read_to_string(format!(
-                    "{}/{}/metadata.json",
-                    dir,
-                    entry.file_name().to_str().expect("Could not get filename!")
-                )) {
-                    let mut saved: SavedSessionData = serde_json::from_str(&contents)?;
-                    if let Ok(transcript) = std::fs::read_to_string(format!(
-                        "{}/{}/{}.txt",
-                        dir,
-                        entry.file_name().to_str().expect("Could not get filename!"),
-                        saved.uuid
-                    )) {
-                        saved.transcript = Some(transcript);
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    log::error!("Couldn't read an entry of {}: {:?}", dir, e);
+                    continue;
+                }
+            };
+            if !entry.path().is_dir() {
+                continue;
+            }
+            match import_session(&dir, &entry.file_name(), next_id) {
+                Ok(true) => next_id += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    log::error!(
+                        "Couldn't import session from {}: {:?}",
+                        entry.path().display(),
+                        e
+                    );
+                    // the session may have been inserted before failing.
+                    if let Err(e) = db::delete_session(&next_id) {
+                        log::error!("Couldn't remove session {}: {:?}", next_id, e);
                     }
-                    saved_sessions.push(saved);
                 }
             }
         }
-        let mut next_id: usize = 0;
-        let mut get_id = move || {
-            let id = next_id;
-            next_id += 1;
-            id
-        };
-        let restored_sessions: Vec<SessionData> = saved_sessions
-            .iter()
-            .map(|s| SessionData {
-                id: get_id(),
-                transcription_sender_tx: None,
-                language: s.language.clone(),
-                uuid: s.uuid,
-                resource: s.resource.clone(),
-                sample_rate: s.sample_rate,
-                valid: false,
-                buffer: vec![],
-                silence_length: 0,
-                sequence_number: 1,
-                last_sequence: Some(1),
-                recording: false,
-                recording_file: Some(format!("{}/{}/{}.wav", dir, s.uuid, s.uuid)),
-                transcript_file: Some(format!("{}/{}/{}.txt", dir, s.uuid, s.uuid)),
-                translations: Arc::new(Mutex::new(TranslationResponses::new_from_string(
-                    match &s.transcript {
-                        Some(s) => s.clone(),
-                        None => "transcript not found! This is probably a bug.".to_string(),
-                    },
-                    s.uuid.to_string(),
-                ))),
-                updated_at: s.updated_at,
-                created_at: s.created_at,
-            })
-            .collect();
-        for restored_session in restored_sessions {
-            SESSIONS
-                .write()
-                .await
-                .insert(restored_session.id, restored_session);
-        }
-        NEXT_USER_ID.store(get_id(), Ordering::Relaxed);
     }
+    NEXT_USER_ID.store(next_id, Ordering::Relaxed);
     Ok(())
 }
+
+/// Imports the session recorded in `dir/name` as `id`, unless there is no
+/// metadata or it is in the database already. Returns whether it was
+/// imported.
+fn import_session(dir: &str, name: &std::ffi::OsStr, id: usize) -> E<bool> {
+    let name = name
+        .to_str()
+        .ok_or_else(|| format!("{:?} isn't a valid directory name", name))?;
+    let contents = match std::fs::read_to_string(format!("{}/{}/metadata.json", dir, name)) {
+        Ok(c) => c,
+        Err(_) => return Ok(false),
+    };
+    let saved: SavedSessionData = serde_json::from_str(&contents)?;
+    if db::find_session_id(&saved.uuid.to_string())?.is_some() {
+        return Ok(false);
+    }
+    log::info!("Importing session {}", saved.uuid);
+    let recording_file = format!("{}/{}/{}.wav", dir, name, saved.uuid);
+    let transcript_file = format!("{}/{}/{}.txt", dir, name, saved.uuid);
+    let segments_file = format!("{}/{}/{}.json", dir, name, saved.uuid);
+    let responses = match std::fs::read_to_string(&segments_file) {
+        Ok(json) => serde_json::from_str(&json)?,
+        // sessions finalized before the segments were saved only have
+        // the plain transcript.
+        Err(_) => TranslationResponses::new_from_string(
+            match std::fs::read_to_string(&transcript_file)
+                .ok()
+                .or(saved.transcript)
+            {
+                Some(t) => t,
+                None => "transcript not found! This is probably a bug.".to_string(),
+            },
+            saved.uuid.to_string(),
+        ),
+    };
+    let sequence_number = responses.sequence_count();
+    let session = SessionData {
+        id,
+        transcription_sender_tx: None,
+        language: saved.language,
+        uuid: saved.uuid,
+        resource: saved.resource,
+        sample_rate: saved.sample_rate,
+        valid: false,
+        buffer: vec![],
+        silence_length: 0,
+        sent_samples: 0,
+        compared_sequence: None,
+        sequence_number,
+        last_sequence: Some(sequence_number.saturating_sub(1)),
+        recording_file: Some(recording_file),
+        transcript_file: Some(transcript_file),
+        segments_file: Some(segments_file),
+        translations: Arc::new(Mutex::new(TranslationResponses::new())),
+        updated_at: saved.updated_at,
+        created_at: saved.created_at,
+        starred: saved.starred,
+        state: SessionState::Finished,
+        user_id: None,
+        assignment_id: None,
+    };
+    db::insert_session(&session)?;
+    for response in responses.iter() {
+        db::insert_segment(session.id, response)?;
+    }
+    db::finalize_session(&session)?;
+    Ok(true)
+}
@@ -0,0 +1,87 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::error::{Er, E};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Longest a share link can be valid for.
+const MAX_SHARE_HOURS: i64 = 24 * 90;
+
+lazy_static! {
+    /// Share links are signed with `SHARE_SECRET`. If it isn't set a random
+    /// secret is used, and links stop working when the server restarts.
+    static ref SECRET: Vec<u8> = match std::env::var("SHARE_SECRET") {
+        Ok(secret) => secret.into_bytes(),
+        Err(_) => {
+            log::warn!("SHARE_SECRET not set, share links won't survive a restart");
+            let mut secret = Uuid::new_v4().as_bytes().to_vec();
+            secret.extend_from_slice(Uuid::new_v4().as_bytes());
+            secret
+        }
+    };
+}
+
+#[derive(Deserialize)]
+pub struct ShareRequest {
+    /// How long the link is valid for, default a week.
+    #[serde(default = "default_hours")]
+    pub hours: i64,
+}
+
+fn default_hours() -> i64 {
+    24 * 7
+}
+
+#[derive(Serialize)]
+pub struct ShareLink {
+    pub token: String,
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+fn mac(uuid: &Uuid, expires: i64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(&SECRET).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}:{}", uuid, expires).as_bytes());
+    mac
+}
+
+/// Makes a token giving read only access to a session's recording,
+/// transcript and comparison until it expires. Tokens are the session's
+/// uuid, the expiry time and a signature of the two, so nothing needs to be
+/// stored.
+pub fn create(uuid: &Uuid, request: &ShareRequest) -> E<ShareLink> {
+    if request.hours < 1 || request.hours > MAX_SHARE_HOURS {
+        return Err(Er::new(format!(
+            "Share links must be valid for between 1 and {} hours",
+            MAX_SHARE_HOURS
+        )));
+    }
+    let expires_at = Utc::now() + Duration::hours(request.hours);
+    let expires = expires_at.timestamp();
+    let signature = hex::encode(mac(uuid, expires).finalize().into_bytes());
+    let token = format!("{}.{}.{}", uuid.simple(), expires, signature);
+    Ok(ShareLink {
+        url: format!("/shared/{}", token),
+        token,
+        expires_at,
+    })
+}
+
+/// The uuid of the session a token shares and when the token expires, if
+/// the signature is good and it hasn't expired yet.
+pub fn verify(token: &str) -> Option<(Uuid, DateTime<Utc>)> {
+    let mut parts = token.splitn(3, '.');
+    let uuid = Uuid::parse_str(parts.next()?).ok()?;
+    let expires: i64 = parts.next()?.parse().ok()?;
+    let signature = hex::decode(parts.next()?).ok()?;
+    mac(&uuid, expires).verify_slice(&signature).ok()?;
+    match Utc.timestamp_opt(expires, 0).single() {
+        Some(expires_at) if expires_at > Utc::now() => Some((uuid, expires_at)),
+        _ => None,
+    }
+}
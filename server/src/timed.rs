@@ -0,0 +1,564 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::compare::CompareError;
+use crate::db;
+use crate::error::{Er, E};
+use crate::metadata::Metadata;
+use crate::session::SessionData;
+
+/// A piece of a timed reference translation. Times are in seconds from the
+/// start of the resource's audio.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Cue {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/// A transcribed segment placed on the resource's timeline, in seconds.
+#[derive(Clone, Debug, Serialize)]
+pub struct TimedSegment {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/// A reference cue and the transcript segments which interpret it.
+#[derive(Clone, Debug, Serialize)]
+pub struct CueComparison {
+    pub start: f64,
+    pub end: f64,
+    pub reference: String,
+    pub transcript: String,
+    /// How many words the two sides share, from 0 to 1.
+    pub similarity: f64,
+    /// Seconds from the start of the cue to the start of its first segment,
+    /// or `None` if nothing interprets it.
+    pub lag: Option<f64>,
+    /// Whether the lag is more than the allowed maximum.
+    pub lagging: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TimedComparison {
+    pub cues: Vec<CueComparison>,
+    /// Segments which are too far from every cue to interpret any of them.
+    pub unmatched: Vec<TimedSegment>,
+    pub mean_lag: Option<f64>,
+    pub max_lag: f64,
+}
+
+#[derive(Deserialize)]
+pub struct TimedQuery {
+    #[serde(default = "default_max_lag")]
+    pub max_lag: f64,
+}
+
+fn default_max_lag() -> f64 {
+    5.0
+}
+
+/// How long before a cue starts a segment may start and still interpret it.
+const EARLY_TOLERANCE: f64 = 1.0;
+
+/// Reads the timed translation of a resource into `lang`. The format
+/// follows the file's extension: `.srt`, `.vtt` or `.json`, the latter an
+/// array of `{"start", "end", "text"}` objects with times in seconds.
+pub fn load(metadata: &Metadata, resource_path: &str, lang: &str) -> E<Vec<Cue>> {
+    let filename = match metadata.timed_translations.get(lang) {
+        Some(f) => f,
+        None => {
+            let mut available: Vec<String> = metadata.timed_translations.keys().cloned().collect();
+            available.sort();
+            return Err(Box::new(CompareError::MissingTranslation {
+                resource: resource_path.to_string(),
+                lang: lang.to_string(),
+                available,
+            }));
+        }
+    };
+    let contents =
+        std::fs::read_to_string(format!("{}/{}", metadata.enclosing_directory, filename))?;
+    parse(filename, &contents)
+}
+
+pub fn parse(filename: &str, contents: &str) -> E<Vec<Cue>> {
+    let extension = Path::new(filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let mut cues: Vec<Cue> = match extension.as_str() {
+        "json" => serde_json::from_str(contents)?,
+        "srt" | "vtt" => parse_subtitles(contents)?,
+        _ => {
+            return Err(Er::new(format!(
+                "Unknown timed translation format: {}",
+                filename
+            )))
+        }
+    };
+    cues.retain(|c| !c.text.trim().is_empty());
+    if let Some(cue) = cues
+        .iter()
+        .find(|c| !(c.start.is_finite() && c.end.is_finite() && 0.0 <= c.start && c.start <= c.end))
+    {
+        return Err(Er::new(format!(
+            "Invalid cue times {} to {} in {}",
+            cue.start, cue.end, filename
+        )));
+    }
+    cues.sort_by(|a, b| a.start.total_cmp(&b.start));
+    Ok(cues)
+}
+
+/// The text of the cues, for comparisons which don't need the times.
+pub fn reference_text(cues: &[Cue]) -> String {
+    cues.iter()
+        .map(|c| c.text.trim())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// SubRip and WebVTT both consist of blocks separated by blank lines, in
+/// which a `start --> end` line is followed by the text. Anything before the
+/// timing line, such as cue numbers, and blocks without one, such as the
+/// WebVTT header and notes, are skipped.
+fn parse_subtitles(contents: &str) -> E<Vec<Cue>> {
+    let contents = contents
+        .trim_start_matches('\u{feff}')
+        .replace("\r\n", "\n");
+    let mut cues = vec![];
+    for block in contents.split("\n\n") {
+        let mut lines = block
+            .lines()
+            .map(str::trim)
+            .skip_while(|l| !l.contains("-->"));
+        let (start, rest) = match lines.next().and_then(|l| l.split_once("-->")) {
+            Some(timing) => timing,
+            None => continue,
+        };
+        // WebVTT puts cue settings after the end time.
+        let end = rest.split_whitespace().next().unwrap_or("");
+        let text: Vec<&str> = lines.filter(|l| !l.is_empty()).collect();
+        cues.push(Cue {
+            start: parse_timestamp(start.trim())?,
+            end: parse_timestamp(end)?,
+            text: strip_tags(&text.join(" ")),
+        });
+    }
+    Ok(cues)
+}
+
+/// Reads `hh:mm:ss,mmm`, `hh:mm:ss.mmm` or `mm:ss.mmm` as seconds. Minutes
+/// and seconds have two digits and are less than 60, and the fraction of a
+/// second may be left out.
+fn parse_timestamp(timestamp: &str) -> E<f64> {
+    let invalid = || Er::new(format!("Invalid timestamp {}", timestamp));
+    let is_number = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    let (clock, fraction) = match timestamp.split_once([',', '.']) {
+        Some((clock, fraction)) if is_number(fraction) => (clock, fraction),
+        Some(_) => return Err(invalid()),
+        None => (timestamp, "0"),
+    };
+    let parts: Vec<&str> = clock.split(':').collect();
+    if !(2..=3).contains(&parts.len()) || !parts.iter().all(|p| is_number(p)) {
+        return Err(invalid());
+    }
+    let mut seconds = 0.0;
+    for (i, part) in parts.iter().enumerate() {
+        let value: f64 = part.parse()?;
+        if i > 0 && (part.len() != 2 || value >= 60.0) {
+            return Err(invalid());
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Ok(seconds + format!("0.{}", fraction).parse::<f64>()?)
+}
+
+/// Removes formatting like `<i>` and WebVTT voice spans.
+fn strip_tags(text: &str) -> String {
+    let mut result = String::new();
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => result.push(c),
+            _ => (),
+        }
+    }
+    result
+}
+
+/// The transcribed segments of a session on the resource's timeline.
+/// Segment times are relative to their sequence, so the start of each
+/// sequence in the recording is added, along with `skip`, the point in the
+/// resource's audio where the recording began. Sessions recorded before
+/// sequence starts were saved place each sequence after the last segment of
+/// the one before.
+pub fn session_segments(session: &SessionData, skip: f64) -> E<Vec<TimedSegment>> {
+    let starts = db::load_sequence_starts(&session.id)?;
+    let translations = session.translations.lock().unwrap();
+    let mut segments = vec![];
+    let mut sequence = None;
+    let mut sequence_start = 0;
+    let mut end_of_previous = 0;
+    for response in translations.iter() {
+        if sequence != Some(response.sequence_number) {
+            sequence = Some(response.sequence_number);
+            sequence_start = match starts.get(&response.sequence_number) {
+                Some(start) => *start,
+                None => end_of_previous,
+            };
+        }
+        end_of_previous = end_of_previous.max(sequence_start + response.segment_end);
+        let text = response.translation.trim();
+        if text.is_empty() {
+            continue;
+        }
+        segments.push(TimedSegment {
+            start: skip + (sequence_start + response.segment_start) as f64 / 1000.0,
+            end: skip + (sequence_start + response.segment_end) as f64 / 1000.0,
+            text: text.to_string(),
+        });
+    }
+    Ok(segments)
+}
+
+/// Matches each segment with the cue it most likely interprets: of the cues
+/// it starts no more than `EARLY_TOLERANCE` before, and no more than twice
+/// `max_lag` after the end of, the one sharing the most words with it, or
+/// the nearest in time if it shares no words with any. Cues whose first
+/// segment starts more than `max_lag` after them are lagging.
+pub fn compare(cues: &[Cue], segments: &[TimedSegment], max_lag: f64) -> TimedComparison {
+    let mut matched: Vec<Vec<&TimedSegment>> = vec![vec![]; cues.len()];
+    let mut unmatched = vec![];
+    for segment in segments {
+        let best = cues
+            .iter()
+            .enumerate()
+            .filter(|(_, cue)| {
+                segment.start >= cue.start - EARLY_TOLERANCE
+                    && segment.start <= cue.end + 2.0 * max_lag
+            })
+            .map(|(i, cue)| {
+                let distance = (segment.start - cue.start).abs();
+                let score = crate::metrics::similarity(&segment.text, &cue.text)
+                    - distance / (1000.0 * (1.0 + max_lag));
+                (i, score)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        match best {
+            Some((i, _)) => matched[i].push(segment),
+            None => unmatched.push(segment.clone()),
+        }
+    }
+
+    let cues: Vec<CueComparison> = cues
+        .iter()
+        .zip(matched)
+        .map(|(cue, segments)| {
+            let transcript = segments
+                .iter()
+                .map(|s| s.text.as_str())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let lag = segments
+                .iter()
+                .map(|s| s.start)
+                .min_by(f64::total_cmp)
+                .map(|start| start - cue.start);
+            CueComparison {
+                start: cue.start,
+                end: cue.end,
+                reference: cue.text.clone(),
+                similarity: crate::metrics::similarity(&transcript, &cue.text),
+                transcript,
+                lag,
+                lagging: lag.is_some_and(|l| l > max_lag),
+            }
+        })
+        .collect();
+    let lags: Vec<f64> = cues.iter().filter_map(|c| c.lag).collect();
+    let mean_lag = if lags.is_empty() {
+        None
+    } else {
+        Some(lags.iter().sum::<f64>() / lags.len() as f64)
+    };
+    TimedComparison {
+        cues,
+        unmatched,
+        mean_lag,
+        max_lag,
+    }
+}
+
+/// The ear-voice span at one point of the speech: how long after the
+/// speaker the interpreter began rendering a cue.
+#[derive(Clone, Debug, Serialize)]
+pub struct SpanPoint {
+    /// When the cue starts, in seconds from the start of the audio.
+    pub time: f64,
+    pub span: f64,
+    pub reference: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct EarVoiceSpan {
+    pub points: Vec<SpanPoint>,
+    pub mean: Option<f64>,
+    pub max: Option<f64>,
+    /// How many seconds the span grows by per minute of speech, from a
+    /// least squares fit. Negative if the interpreter catches up.
+    pub trend: Option<f64>,
+}
+
+/// The ear-voice span over the course of the speech, taken from the cues
+/// whose segments share words with them, as those matched on timing alone
+/// may not be renderings of the cue at all.
+pub fn ear_voice_span(comparison: &TimedComparison) -> EarVoiceSpan {
+    let points: Vec<SpanPoint> = comparison
+        .cues
+        .iter()
+        .filter(|c| c.similarity > 0.0)
+        .filter_map(|c| {
+            c.lag.map(|span| SpanPoint {
+                time: c.start,
+                span,
+                reference: c.reference.clone(),
+            })
+        })
+        .collect();
+    if points.is_empty() {
+        return EarVoiceSpan {
+            points,
+            mean: None,
+            max: None,
+            trend: None,
+        };
+    }
+    let n = points.len() as f64;
+    let mean_time = points.iter().map(|p| p.time).sum::<f64>() / n;
+    let mean = points.iter().map(|p| p.span).sum::<f64>() / n;
+    let max = points.iter().map(|p| p.span).fold(f64::MIN, f64::max);
+    let variance: f64 = points.iter().map(|p| (p.time - mean_time).powi(2)).sum();
+    let trend = if variance > 0.0 {
+        let covariance: f64 = points
+            .iter()
+            .map(|p| (p.time - mean_time) * (p.span - mean))
+            .sum();
+        Some(60.0 * covariance / variance)
+    } else {
+        None
+    };
+    EarVoiceSpan {
+        points,
+        mean: Some(mean),
+        max: Some(max),
+        trend,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cue(start: f64, end: f64, text: &str) -> Cue {
+        Cue {
+            start,
+            end,
+            text: text.to_string(),
+        }
+    }
+
+    fn segment(start: f64, end: f64, text: &str) -> TimedSegment {
+        TimedSegment {
+            start,
+            end,
+            text: text.to_string(),
+        }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn timestamps() {
+        assert!(close(parse_timestamp("00:01:02,500").unwrap(), 62.5));
+        assert!(close(parse_timestamp("00:01:02.500").unwrap(), 62.5));
+        assert!(close(parse_timestamp("01:02.25").unwrap(), 62.25));
+        assert!(close(parse_timestamp("1:00:00.000").unwrap(), 3600.0));
+        assert!(close(parse_timestamp("100:00:01").unwrap(), 360_001.0));
+    }
+
+    #[test]
+    fn malformed_timestamps() {
+        for timestamp in [
+            "",
+            "12",
+            "00:00:00:01,000",
+            "00:1:02,000",
+            "00:60:00,000",
+            "00:00:60,000",
+            "00:00:01,",
+            "00:00:01,5x",
+            "00:00:01.000.000",
+            "-00:00:01,000",
+            "aa:bb:cc,ddd",
+            "00: 00:01,000",
+            "inf",
+            "NaN",
+        ] {
+            assert!(
+                parse_timestamp(timestamp).is_err(),
+                "{:?} should be invalid",
+                timestamp
+            );
+        }
+    }
+
+    #[test]
+    fn subrip() {
+        let srt = "\u{feff}1\r\n00:00:01,000 --> 00:00:03,500\r\nGuten Morgen,\r\n\
+                   <i>meine Damen</i> und Herren.\r\n\r\n\
+                   2\r\n00:00:04,000 --> 00:00:06,000\r\nWillkommen.\r\n";
+        let cues = parse_subtitles(srt).unwrap();
+        assert_eq!(cues.len(), 2);
+        assert!(close(cues[0].start, 1.0) && close(cues[0].end, 3.5));
+        assert_eq!(cues[0].text, "Guten Morgen, meine Damen und Herren.");
+        assert_eq!(cues[1].text, "Willkommen.");
+    }
+
+    #[test]
+    fn webvtt() {
+        let vtt = "WEBVTT\n\nNOTE made by hand\n\n\
+                   intro\n00:01.000 --> 00:02.500 align:start position:10%\n\
+                   <v Speaker>Hello there.\n\n\
+                   00:00:03.000 --> 00:00:04.000\nGoodbye.\n";
+        let cues = parse_subtitles(vtt).unwrap();
+        assert_eq!(cues.len(), 2);
+        assert!(close(cues[0].start, 1.0) && close(cues[0].end, 2.5));
+        assert_eq!(cues[0].text, "Hello there.");
+        assert!(close(cues[1].start, 3.0));
+    }
+
+    #[test]
+    fn subtitles_with_malformed_timings() {
+        assert!(parse_subtitles("1\n00:00:01,000 --> 00:00:0x,000\nText\n").is_err());
+        assert!(parse_subtitles("1\n00:00:01,000 -->\nText\n").is_err());
+        assert!(parse_subtitles("1\n1 --> 2\nText\n").is_err());
+        // blocks without a timing line aren't cues at all.
+        assert!(parse_subtitles("1\nJust text\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn formats() {
+        let json = r#"[{"start": 5, "end": 6, "text": "second"},
+                       {"start": 1, "end": 2, "text": "first"},
+                       {"start": 3, "end": 4, "text": "  "}]"#;
+        let cues = parse("de.json", json).unwrap();
+        let texts: Vec<&str> = cues.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, vec!["first", "second"]);
+        assert_eq!(reference_text(&cues), "first\nsecond");
+        assert!(parse("de.SRT", "1\n00:00:01,000 --> 00:00:02,000\nHallo\n").is_ok());
+        assert!(parse("de.txt", "Hallo").is_err());
+        assert!(parse("de.json", "not json").is_err());
+        assert!(parse("de.json", r#"[{"start": 2, "end": 1, "text": "x"}]"#).is_err());
+        assert!(parse("de.json", r#"[{"start": -1, "end": 1, "text": "x"}]"#).is_err());
+        assert!(parse("de.json", r#"[{"start": 1, "end": 1e400, "text": "x"}]"#).is_err());
+        assert!(parse("de.srt", "1\n00:00:02,000 --> 00:00:01,000\nHallo\n").is_err());
+    }
+
+    #[test]
+    fn comparing_segments_with_cues() {
+        let cues = [
+            cue(0.0, 4.0, "Good morning ladies and gentlemen"),
+            cue(5.0, 9.0, "Welcome to the conference"),
+            cue(10.0, 14.0, "Let us begin"),
+        ];
+        let segments = [
+            segment(1.5, 4.0, "good morning ladies and gentlemen"),
+            // a little early, which is allowed.
+            segment(4.5, 8.0, "welcome to the conference"),
+            segment(60.0, 62.0, "thank you"),
+        ];
+        let comparison = compare(&cues, &segments, 5.0);
+        assert_eq!(comparison.cues.len(), 3);
+        assert!(close(comparison.cues[0].lag.unwrap(), 1.5));
+        assert!(close(comparison.cues[0].similarity, 1.0));
+        assert!(close(comparison.cues[1].lag.unwrap(), -0.5));
+        assert_eq!(comparison.cues[2].lag, None);
+        assert_eq!(comparison.cues[2].transcript, "");
+        assert!(comparison.cues.iter().all(|c| !c.lagging));
+        assert_eq!(comparison.unmatched.len(), 1);
+        assert_eq!(comparison.unmatched[0].text, "thank you");
+        assert!(close(comparison.mean_lag.unwrap(), 0.5));
+    }
+
+    #[test]
+    fn lagging_cues() {
+        let cues = [
+            cue(0.0, 3.0, "the first point"),
+            cue(3.0, 6.0, "the second point"),
+        ];
+        let segments = [segment(8.0, 10.0, "the second point")];
+        let comparison = compare(&cues, &segments, 2.0);
+        // shared words outweigh the nearer cue.
+        assert_eq!(comparison.cues[1].transcript, "the second point");
+        assert!(close(comparison.cues[1].lag.unwrap(), 5.0));
+        assert!(comparison.cues[1].lagging);
+        assert_eq!(comparison.cues[0].lag, None);
+    }
+
+    fn with_lags(lags: &[(f64, f64)]) -> TimedComparison {
+        let cues: Vec<Cue> = lags
+            .iter()
+            .map(|(time, _)| cue(*time, time + 5.0, "words"))
+            .collect();
+        let segments: Vec<TimedSegment> = lags
+            .iter()
+            .map(|(time, lag)| segment(time + lag, time + lag + 2.0, "words"))
+            .collect();
+        compare(&cues, &segments, 10.0)
+    }
+
+    #[test]
+    fn growing_ear_voice_span() {
+        let span = ear_voice_span(&with_lags(&[(0.0, 1.0), (60.0, 2.0), (120.0, 3.0)]));
+        assert_eq!(span.points.len(), 3);
+        assert!(close(span.mean.unwrap(), 2.0));
+        assert!(close(span.max.unwrap(), 3.0));
+        // a second more every minute.
+        assert!(close(span.trend.unwrap(), 1.0));
+    }
+
+    #[test]
+    fn shrinking_and_steady_ear_voice_spans() {
+        let span = ear_voice_span(&with_lags(&[(0.0, 4.0), (30.0, 3.0), (60.0, 2.0)]));
+        assert!(close(span.trend.unwrap(), -2.0));
+        let span = ear_voice_span(&with_lags(&[(0.0, 2.0), (30.0, 2.0), (90.0, 2.0)]));
+        assert!(close(span.trend.unwrap(), 0.0));
+    }
+
+    #[test]
+    fn ear_voice_span_without_enough_points() {
+        let span = ear_voice_span(&with_lags(&[(0.0, 2.0)]));
+        assert!(close(span.mean.unwrap(), 2.0));
+        assert_eq!(span.trend, None);
+
+        let span = ear_voice_span(&with_lags(&[]));
+        assert!(span.points.is_empty());
+        assert_eq!((span.mean, span.max, span.trend), (None, None, None));
+
+        // segments matched on timing alone don't count.
+        let comparison = compare(
+            &[cue(0.0, 5.0, "good morning")],
+            &[segment(1.0, 2.0, "hello")],
+            5.0,
+        );
+        assert!(ear_voice_span(&comparison).points.is_empty());
+    }
+}
@@ -17,7 +17,7 @@ pub struct TranslationRequest {
     pub lang: String,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TranslationResponse {
     pub sequence_number: usize,
     pub translation: String,
@@ -28,13 +28,13 @@ pub struct TranslationResponse {
     pub uuid: String,
 }
 
-impl ToString for TranslationResponse {
-    fn to_string(&self) -> String {
-        self.translation.clone()
+impl std::fmt::Display for TranslationResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.translation)
     }
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TranslationResponses(Vec<Option<Vec<Option<TranslationResponse>>>>);
 
 impl TranslationResponses {
@@ -78,17 +78,28 @@ impl TranslationResponses {
         Ok(())
     }
 
+    /// The number of sequences, transcribed or not, seen so far.
+    pub fn sequence_count(&self) -> usize {
+        self.0.len()
+    }
+
+    /// All the segments received so far, in order.
+    pub fn iter(&self) -> impl Iterator<Item = &TranslationResponse> {
+        self.0.iter().flatten().flatten().flatten()
+    }
+
     pub fn translation_count(&self) -> E<usize> {
         let count = self.0.iter().filter(|x| !x.is_none()).count();
         Ok(count)
     }
 }
 
-impl ToString for TranslationResponses {
-    fn to_string(&self) -> String {
+impl TranslationResponses {
+    /// The transcript of the first `sequences` sequences.
+    pub fn text_until(&self, sequences: usize) -> String {
         let mut result = String::new();
 
-        for responses in self.0.iter() {
+        for responses in self.0.iter().take(sequences) {
             match responses {
                 Some(sequences) => {
                     for sequence in sequences.iter() {
@@ -105,6 +116,12 @@ impl ToString for TranslationResponses {
     }
 }
 
+impl std::fmt::Display for TranslationResponses {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.text_until(self.0.len()))
+    }
+}
+
 pub const SEND_SAMPLE_MINIMUM_TIME_SECONDS: usize = 15;
 pub const SILENCE_TIME_MILLISECONDS: usize = 200;
 pub const SILENCE_AMPLITUDE_THRESHOLD: f32 = 0.005;
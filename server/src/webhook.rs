@@ -0,0 +1,223 @@
+use hmac::{Hmac, Mac};
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use serde::Serialize;
+use sha2::Sha256;
+use std::sync::OnceLock;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::error::{Er, E};
+use crate::fluency::Fluency;
+use crate::metrics::Metrics;
+use crate::session::SessionData;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// `sha256=` followed by the hex HMAC-SHA256 of the body, keyed with the
+/// secret.
+const SIGNATURE_HEADER: &str = "X-Terplounge-Signature";
+/// The same for every attempt to deliver a notification, so receivers can
+/// ignore ones they already have.
+const DELIVERY_HEADER: &str = "X-Terplounge-Delivery";
+
+/// How long a webhook has to answer.
+const TIMEOUT_SECONDS: u64 = 10;
+
+static CONFIG: OnceLock<WebhookConfig> = OnceLock::new();
+
+/// The webhooks told when a session is finished. Configured with these
+/// environment variables:
+/// - `WEBHOOK_URLS` the URLs to post to, separated by commas. If it isn't
+///   set no webhooks are called.
+/// - `WEBHOOK_SECRET` if set, requests are signed with it in the
+///   `X-Terplounge-Signature` header.
+/// - `WEBHOOK_ATTEMPTS` how many times a request is tried before giving up,
+///   default 5. Requests the webhook rejects with a 4xx status other than
+///   429 aren't tried again.
+/// - `WEBHOOK_RETRY_SECONDS` how long to wait before trying again, doubling
+///   after each attempt, default 2.
+#[derive(Clone)]
+pub struct WebhookConfig {
+    pub urls: Vec<String>,
+    pub secret: Option<String>,
+    pub attempts: u32,
+    pub retry_delay: Duration,
+}
+
+impl WebhookConfig {
+    pub fn from_env() -> E<Self> {
+        let urls = match std::env::var("WEBHOOK_URLS") {
+            Ok(urls) => urls
+                .split(',')
+                .map(|url| url.trim().to_string())
+                .filter(|url| !url.is_empty())
+                .collect(),
+            Err(_) => vec![],
+        };
+        let attempts: u32 = match std::env::var("WEBHOOK_ATTEMPTS") {
+            Ok(attempts) if !attempts.trim().is_empty() => attempts.trim().parse()?,
+            _ => 5,
+        };
+        let retry_seconds: u64 = match std::env::var("WEBHOOK_RETRY_SECONDS") {
+            Ok(seconds) if !seconds.trim().is_empty() => seconds.trim().parse()?,
+            _ => 2,
+        };
+        Ok(Self {
+            urls,
+            secret: std::env::var("WEBHOOK_SECRET")
+                .ok()
+                .filter(|secret| !secret.is_empty()),
+            attempts: attempts.max(1),
+            retry_delay: Duration::from_secs(retry_seconds),
+        })
+    }
+}
+
+pub fn init(config: WebhookConfig) {
+    if !config.urls.is_empty() && config.secret.is_none() {
+        log::warn!("WEBHOOK_SECRET not set, webhook requests won't be signed");
+    }
+    if CONFIG.set(config).is_err() {
+        log::warn!("Webhooks already configured");
+    }
+}
+
+/// The body posted to the webhooks.
+#[derive(Serialize)]
+struct SessionFinished<'a> {
+    event: &'static str,
+    session: &'a SessionData,
+    assignment_id: Option<usize>,
+    transcript: String,
+    /// Against the reference translation of the session's resource, if it
+    /// has one in the session's language.
+    metrics: Option<Metrics>,
+    fluency: Option<Fluency>,
+}
+
+fn payload(session: &SessionData) -> E<String> {
+    let transcript = session.transcript()?;
+    let metrics = match &session.resource {
+        Some(resource) => match crate::compare::get_translation(resource, &session.language) {
+            Ok(reference) => Some(crate::metrics::compute(&transcript, &reference)),
+            Err(e) => {
+                log::debug!("No metrics for session {}: {:?}", session.uuid, e);
+                None
+            }
+        },
+        None => None,
+    };
+    let fluency = match crate::fluency::analyse(session) {
+        Ok(fluency) => Some(fluency),
+        Err(e) => {
+            log::debug!("No fluency for session {}: {:?}", session.uuid, e);
+            None
+        }
+    };
+    Ok(serde_json::to_string(&SessionFinished {
+        event: "session.finished",
+        session,
+        assignment_id: session.assignment_id,
+        transcript,
+        metrics,
+        fluency,
+    })?)
+}
+
+fn signature(secret: &str, body: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Posts the finished session to every webhook, in the background so that
+/// transcription isn't held up.
+pub fn notify(session: &SessionData) {
+    let config = match CONFIG.get() {
+        Some(config) if !config.urls.is_empty() => config.clone(),
+        _ => return,
+    };
+    let session = session.clone();
+    std::thread::spawn(move || {
+        let body = match payload(&session) {
+            Ok(body) => body,
+            Err(e) => {
+                log::error!(
+                    "Couldn't make webhook payload for session {}: {:?}",
+                    session.uuid,
+                    e
+                );
+                return;
+            }
+        };
+        let client = match Client::builder()
+            .timeout(Duration::from_secs(TIMEOUT_SECONDS))
+            .build()
+        {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Couldn't make webhook client: {:?}", e);
+                return;
+            }
+        };
+        for url in &config.urls {
+            match deliver(&client, &config, url, &body) {
+                Ok(_) => log::info!("Told {} session {} is finished", url, session.uuid),
+                Err(e) => log::error!(
+                    "Couldn't tell {} session {} is finished: {}",
+                    url,
+                    session.uuid,
+                    e
+                ),
+            }
+        }
+    });
+}
+
+fn deliver(client: &Client, config: &WebhookConfig, url: &str, body: &str) -> E<()> {
+    let delivery = Uuid::new_v4().to_string();
+    let mut delay = config.retry_delay;
+    for attempt in 1..=config.attempts {
+        let mut request = client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(DELIVERY_HEADER, &delivery)
+            .body(body.to_string());
+        if let Some(secret) = &config.secret {
+            request = request.header(SIGNATURE_HEADER, signature(secret, body));
+        }
+        match request.send() {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response)
+                if response.status().is_client_error()
+                    && response.status() != StatusCode::TOO_MANY_REQUESTS =>
+            {
+                return Err(Er::new(format!("rejected with {}", response.status())));
+            }
+            Ok(response) => log::warn!(
+                "Webhook {} answered {}, attempt {} of {}",
+                url,
+                response.status(),
+                attempt,
+                config.attempts
+            ),
+            Err(e) => log::warn!(
+                "Webhook {} failed: {}, attempt {} of {}",
+                url,
+                e,
+                attempt,
+                config.attempts
+            ),
+        }
+        if attempt < config.attempts {
+            std::thread::sleep(delay);
+            delay *= 2;
+        }
+    }
+    Err(Er::new(format!(
+        "gave up after {} attempts",
+        config.attempts
+    )))
+}
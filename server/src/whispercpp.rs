@@ -66,12 +66,16 @@ impl Translator for WhisperCpp {
                 Ok(text) => text,
                 Err(_) => "<b>error transcribing</b>".to_string(),
             };
-            let start_timestamp = state
-                .full_get_segment_t0(i)
-                .expect("failed to get start timestamp");
-            let end_timestamp = state
-                .full_get_segment_t1(i)
-                .expect("failed to get end timestamp");
+            // whisper.cpp counts in hundredths of a second, segments are
+            // stored in milliseconds.
+            let start_timestamp = 10
+                * state
+                    .full_get_segment_t0(i)
+                    .expect("failed to get start timestamp");
+            let end_timestamp = 10
+                * state
+                    .full_get_segment_t1(i)
+                    .expect("failed to get end timestamp");
 
             log::debug!("[{} - {}]: {}", start_timestamp, end_timestamp, segment);
 
@@ -90,6 +94,10 @@ impl Translator for WhisperCpp {
                 Ok(_) => (),
                 Err(e) => {
                     log::warn!("Processing translation failed with error {}", e);
+                    crate::events::publish_error(
+                        translation_request.session_id,
+                        format!("Couldn't process transcription: {}", e),
+                    );
                     crate::session::mutate_session_sync(
                         &translation_request.session_id,
                         |session| session.valid = false,
@@ -2,9 +2,9 @@ use log::debug;
 use reqwest::blocking::Client;
 use serde::Deserialize;
 use serde_json::json;
-use warp::ws::Message;
 
 use crate::error::{Er, E};
+use crate::events::{self, SessionEvent};
 use crate::translate::{resample, TranslationRequest, TranslationResponse, Translator};
 
 #[derive(Deserialize, Debug)]
@@ -65,12 +65,7 @@ impl Translator for WhisperX {
                 uuid: session.uuid.to_string(),
             };
 
-            match session
-                .transcription_sender_tx
-                .as_ref()
-                .expect("Couldn't find sender!")
-                .send(Message::text(json!(response).to_string()))
-            {
+            match events::send(&session, SessionEvent::Segment(response)) {
                 Ok(_) => (),
                 Err(e) => {
                     log::warn!("Sending failed with error {}", e);